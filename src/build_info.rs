@@ -0,0 +1,324 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+/// Settings of a build, saved as `NAME.build.json` next to its `.dzi` file
+/// so that a rebuild, even from someone else, produces matching tiles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildInfo {
+    pub tile_size: usize,
+    pub overlap: usize,
+    pub format: String,
+    pub quality: u8,
+    /// Input images, in the order they are stitched,
+    /// relative to the directory of the `.dzi` file unless they are absolute paths or URLs.
+    pub inputs: Vec<String>,
+    /// Other settings changing the tiles, by flag name, with their command line values.
+    pub settings: BTreeMap<String, String>,
+    /// Levels kept in the tiles directory, if recorded.
+    /// They are saved as `min_level` and `max_level`.
+    pub levels: Option<RangeInclusive<usize>>,
+    /// Small image of the whole panorama, if one was generated.
+    pub overview: Option<Overview>,
+    /// Tiles not written because they were entirely of the skip color, as (level, tx, ty).
+    /// They are saved as `"level/tx_ty"` strings.
+    pub skipped_tiles: Vec<(usize, usize, usize)>,
+}
+
+/// Overview image saved next to the `.dzi` file, for viewers showing a minimap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Overview {
+    /// Path of the image relative to the directory of the `.dzi` file.
+    pub file: String,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl BuildInfo {
+    /// Load a build info file, or return None if there is none.
+    pub fn load(path: &Path) -> anyhow::Result<Option<Self>> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err).context("Failed to read the build info"),
+        };
+        Self::parse(&content)
+            .with_context(|| format!("Invalid build info {}", path.display()))
+            .map(Some)
+    }
+
+    /// Parse the flat JSON object written by [`BuildInfo::save`].
+    /// Unknown fields are ignored, for files written by later versions.
+    pub fn parse(content: &str) -> anyhow::Result<Self> {
+        let mut parser = Parser { rest: content };
+        let (mut tile_size, mut overlap, mut format, mut quality, mut inputs) =
+            (None, None, None, None, None);
+        let mut settings = BTreeMap::new();
+        let mut skipped_tiles = Vec::new();
+        let (mut overview, mut overview_width, mut overview_height) = (None, None, None);
+        let (mut min_level, mut max_level) = (None, None);
+        parser.expect('{')?;
+        if !parser.eat('}') {
+            loop {
+                let key = parser.string()?;
+                parser.expect(':')?;
+                match key.as_str() {
+                    "tile_size" => tile_size = Some(parser.number()?),
+                    "overlap" => overlap = Some(parser.number()?),
+                    "format" => format = Some(parser.string()?),
+                    "quality" => quality = Some(u8::try_from(parser.number()?)?),
+                    "inputs" => inputs = Some(parser.strings()?),
+                    "settings" => settings = parser.strings_map()?,
+                    "min_level" => min_level = Some(parser.number()?),
+                    "max_level" => max_level = Some(parser.number()?),
+                    "overview" => overview = Some(parser.string()?),
+                    "overview_width" => overview_width = Some(parser.number()?),
+                    "overview_height" => overview_height = Some(parser.number()?),
+                    "skipped_tiles" => {
+                        skipped_tiles = parser
+                            .strings()?
+                            .iter()
+                            .map(|tile| parse_tile(tile))
+                            .collect::<anyhow::Result<_>>()?
+                    }
+                    _ => parser.value()?,
+                }
+                if !parser.eat(',') {
+                    break;
+                }
+            }
+            parser.expect('}')?;
+        }
+        let overview = match overview {
+            Some(file) => Some(Overview {
+                file,
+                width: overview_width.context("Missing overview_width")?,
+                height: overview_height.context("Missing overview_height")?,
+            }),
+            None => None,
+        };
+        let levels = match (min_level, max_level) {
+            (Some(min), Some(max)) => Some(min..=max),
+            (None, None) => None,
+            _ => anyhow::bail!("Expected both min_level and max_level"),
+        };
+        Ok(Self {
+            tile_size: tile_size.context("Missing tile_size")?,
+            overlap: overlap.context("Missing overlap")?,
+            format: format.context("Missing format")?,
+            quality: quality.context("Missing quality")?,
+            inputs: inputs.context("Missing inputs")?,
+            settings,
+            levels,
+            overview,
+            skipped_tiles,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let inputs: Vec<String> = self
+            .inputs
+            .iter()
+            .map(|input| format!("    {}", json_string(input)))
+            .collect();
+        let levels = match &self.levels {
+            Some(levels) => format!(
+                ",\n  \"min_level\": {},\n  \"max_level\": {}",
+                levels.start(),
+                levels.end()
+            ),
+            None => String::new(),
+        };
+        let overview = match &self.overview {
+            Some(overview) => format!(
+                ",\n  \"overview\": {},\n  \"overview_width\": {},\n  \"overview_height\": {}",
+                json_string(&overview.file),
+                overview.width,
+                overview.height
+            ),
+            None => String::new(),
+        };
+        let skipped_tiles = if self.skipped_tiles.is_empty() {
+            String::new()
+        } else {
+            let tiles: Vec<String> = self
+                .skipped_tiles
+                .iter()
+                .map(|(level, tx, ty)| format!("    \"{}/{}_{}\"", level, tx, ty))
+                .collect();
+            format!(",\n  \"skipped_tiles\": [\n{}\n  ]", tiles.join(",\n"))
+        };
+        let settings: Vec<String> = self
+            .settings
+            .iter()
+            .map(|(flag, value)| format!("    {}: {}", json_string(flag), json_string(value)))
+            .collect();
+        let settings = if settings.is_empty() {
+            "{}".to_string()
+        } else {
+            format!("{{\n{}\n  }}", settings.join(",\n"))
+        };
+        let content = format!(
+            "{{\n  \"tile_size\": {},\n  \"overlap\": {},\n  \"format\": {},\n  \"quality\": {},\n  \"inputs\": [\n{}\n  ],\n  \"settings\": {}{}{}{}\n}}\n",
+            self.tile_size,
+            self.overlap,
+            json_string(&self.format),
+            self.quality,
+            inputs.join(",\n"),
+            settings,
+            levels,
+            overview,
+            skipped_tiles
+        );
+        std::fs::write(path, content).context("Failed to write the build info")
+    }
+}
+
+/// Parse a tile saved as `"level/tx_ty"`.
+fn parse_tile(tile: &str) -> anyhow::Result<(usize, usize, usize)> {
+    let parse = || -> Option<(usize, usize, usize)> {
+        let (level, coords) = tile.split_once('/')?;
+        let (tx, ty) = coords.split_once('_')?;
+        Some((level.parse().ok()?, tx.parse().ok()?, ty.parse().ok()?))
+    };
+    parse().with_context(|| format!("Invalid skipped tile {}", tile))
+}
+
+/// Quote and escape a JSON string.
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Minimal JSON parser for the values found in build info files.
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl Parser<'_> {
+    /// Skip whitespace and consume the given character if it comes next.
+    fn eat(&mut self, c: char) -> bool {
+        self.rest = self.rest.trim_start();
+        match self.rest.strip_prefix(c) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn expect(&mut self, c: char) -> anyhow::Result<()> {
+        if !self.eat(c) {
+            anyhow::bail!("Expected '{}'", c);
+        }
+        Ok(())
+    }
+
+    fn number(&mut self) -> anyhow::Result<usize> {
+        self.rest = self.rest.trim_start();
+        let end = self
+            .rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(self.rest.len());
+        let (digits, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        digits.parse().context("Expected a number")
+    }
+
+    fn string(&mut self) -> anyhow::Result<String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        let mut chars = self.rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.rest = &self.rest[i + 1..];
+                    return Ok(s);
+                }
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some('r') => s.push('\r'),
+                    Some('b') => s.push('\u{8}'),
+                    Some('f') => s.push('\u{c}'),
+                    Some('u') => {
+                        let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                        let code = u32::from_str_radix(&hex, 16).context("Invalid \\u escape")?;
+                        s.push(char::from_u32(code).context("Invalid \\u escape")?);
+                    }
+                    Some(c) => s.push(c),
+                    None => break,
+                },
+                c => s.push(c),
+            }
+        }
+        anyhow::bail!("Unterminated string")
+    }
+
+    fn strings(&mut self) -> anyhow::Result<Vec<String>> {
+        self.expect('[')?;
+        let mut strings = Vec::new();
+        if self.eat(']') {
+            return Ok(strings);
+        }
+        loop {
+            strings.push(self.string()?);
+            if !self.eat(',') {
+                break;
+            }
+        }
+        self.expect(']')?;
+        Ok(strings)
+    }
+
+    /// Object whose values are all strings.
+    fn strings_map(&mut self) -> anyhow::Result<BTreeMap<String, String>> {
+        self.expect('{')?;
+        let mut map = BTreeMap::new();
+        if self.eat('}') {
+            return Ok(map);
+        }
+        loop {
+            let key = self.string()?;
+            self.expect(':')?;
+            map.insert(key, self.string()?);
+            if !self.eat(',') {
+                break;
+            }
+        }
+        self.expect('}')?;
+        Ok(map)
+    }
+
+    /// Skip a value of an unknown field, a number, string, list of strings
+    /// or object of strings.
+    fn value(&mut self) -> anyhow::Result<()> {
+        self.rest = self.rest.trim_start();
+        if self.rest.starts_with('"') {
+            self.string()?;
+        } else if self.rest.starts_with('[') {
+            self.strings()?;
+        } else if self.rest.starts_with('{') {
+            self.strings_map()?;
+        } else {
+            self.number()?;
+        }
+        Ok(())
+    }
+}
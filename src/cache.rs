@@ -0,0 +1,121 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::Context;
+use std::ops::Range;
+use std::path::Path;
+
+/// 64 bits FNV-1a hash of some content, used to detect changed inputs.
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// State of one input image at the time of a build.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheEntry {
+    pub path: String,
+    /// Hash of the image content, combined with its placement in the panorama.
+    pub hash: u64,
+    /// Columns of the panorama, in pixels, covered by the image.
+    pub columns: Range<usize>,
+}
+
+/// Inputs of a previous build, stored next to its tiles so that a rebuild
+/// only regenerates the tiles of the inputs that changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildCache {
+    /// Hash of all the settings affecting the tiles content.
+    pub settings: u64,
+    /// Width and height of the panorama in pixels.
+    pub size: (usize, usize),
+    /// Number of levels of the panorama, which shift with its size.
+    pub levels: usize,
+    pub entries: Vec<CacheEntry>,
+}
+
+impl BuildCache {
+    /// Load a cache file, or return None if there is none.
+    pub fn load(path: &Path) -> anyhow::Result<Option<Self>> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err).context("Failed to read the build cache"),
+        };
+        Self::parse(&content)
+            .with_context(|| format!("Invalid build cache {}", path.display()))
+            .map(Some)
+    }
+
+    fn parse(content: &str) -> anyhow::Result<Self> {
+        let mut lines = content.lines();
+        let settings = lines
+            .next()
+            .and_then(|line| line.strip_prefix("settings "))
+            .context("Missing settings line")?;
+        let settings = u64::from_str_radix(settings, 16)?;
+        let panorama = lines
+            .next()
+            .and_then(|line| line.strip_prefix("panorama "))
+            .context("Missing panorama line")?;
+        let (size, levels) = match panorama.split(' ').collect::<Vec<_>>()[..] {
+            [width, height, levels] => ((width.parse()?, height.parse()?), levels.parse()?),
+            _ => anyhow::bail!("Expected WIDTH HEIGHT LEVELS"),
+        };
+        let mut entries = Vec::new();
+        for line in lines {
+            let mut fields = line.splitn(4, ' ');
+            let mut field = || fields.next().context("Missing field");
+            let hash = u64::from_str_radix(field()?, 16)?;
+            let start = field()?.parse()?;
+            let end = field()?.parse()?;
+            let path = field()?.to_string();
+            entries.push(CacheEntry {
+                path,
+                hash,
+                columns: start..end,
+            });
+        }
+        Ok(Self {
+            settings,
+            size,
+            levels,
+            entries,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let mut content = format!(
+            "settings {:016x}\npanorama {} {} {}\n",
+            self.settings, self.size.0, self.size.1, self.levels
+        );
+        for entry in &self.entries {
+            content += &format!(
+                "{:016x} {} {} {}\n",
+                entry.hash, entry.columns.start, entry.columns.end, entry.path
+            );
+        }
+        std::fs::write(path, content).context("Failed to write the build cache")
+    }
+
+    /// Columns of the panorama, in pixels, whose content changed since the previous build.
+    /// Return None if everything must be regenerated, like when the panorama size changed,
+    /// which changes all the levels below the base one and may shift their numbers.
+    pub fn changed_columns(&self, previous: &Self) -> Option<Vec<Range<usize>>> {
+        if self.settings != previous.settings
+            || self.size != previous.size
+            || self.levels != previous.levels
+            || self.entries.len() != previous.entries.len()
+        {
+            return None;
+        }
+        let changed = self
+            .entries
+            .iter()
+            .zip(&previous.entries)
+            .filter(|(entry, old)| entry.hash != old.hash || entry.columns != old.columns)
+            .flat_map(|(entry, old)| [old.columns.clone(), entry.columns.clone()])
+            .collect();
+        Some(changed)
+    }
+}
@@ -0,0 +1,164 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::path::Path;
+
+/// Name of the manifest of tile hashes, next to the .dzi files.
+pub const CHECKSUMS_FILE: &str = "checksums.txt";
+
+/// SHA-256 hashes of files, saved in the format of `sha256sum`
+/// with paths relative to the directory of the manifest,
+/// so that `sha256sum -c checksums.txt` also checks them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Checksums {
+    /// Hexadecimal hash of each file, by path.
+    pub entries: BTreeMap<String, String>,
+}
+
+impl Checksums {
+    /// Load a manifest, or return an empty one if there is none.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err).context("Failed to read the checksums"),
+        };
+        Self::parse(&content).with_context(|| format!("Invalid checksums {}", path.display()))
+    }
+
+    fn parse(content: &str) -> anyhow::Result<Self> {
+        let mut entries = BTreeMap::new();
+        for line in content.lines() {
+            let (hash, path) = line.split_once("  ").context("Missing path")?;
+            if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+                anyhow::bail!("Invalid hash {}", hash);
+            }
+            entries.insert(path.to_string(), hash.to_string());
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let mut content = String::new();
+        for (file, hash) in &self.entries {
+            content += &format!("{}  {}\n", hash, file);
+        }
+        std::fs::write(path, content).context("Failed to write the checksums")
+    }
+
+    /// Check the files of the manifest in `dir` which are in the `prefix` directory,
+    /// returning the paths of the missing ones and of the ones whose hash differs.
+    pub fn verify(&self, dir: &Path, prefix: &Path) -> Vec<(String, ChecksumProblem)> {
+        use rayon::prelude::*;
+        let files: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|(file, _)| Path::new(file).starts_with(prefix))
+            .collect();
+        files
+            .into_par_iter()
+            .filter_map(|(file, hash)| match std::fs::read(dir.join(file)) {
+                Err(_) => Some((file.clone(), ChecksumProblem::Missing)),
+                Ok(bytes) if sha256_hex(&bytes) != *hash => {
+                    Some((file.clone(), ChecksumProblem::Mismatch))
+                }
+                Ok(_) => None,
+            })
+            .collect()
+    }
+}
+
+/// What is wrong with a file of a checksums manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumProblem {
+    Missing,
+    Mismatch,
+}
+
+impl fmt::Display for ChecksumProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Missing => write!(f, "missing"),
+            Self::Mismatch => write!(f, "checksum mismatch"),
+        }
+    }
+}
+
+/// Hexadecimal SHA-256 hash of some content.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    sha256(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// SHA-256 hash of some content, as specified in FIPS 180-4.
+fn sha256(bytes: &[u8]) -> [u8; 32] {
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+    // Pad with a 1 bit, zeros, and the length in bits, to a multiple of 64 bytes.
+    let mut message = bytes.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(bytes.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (s, v) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    let mut hash = [0; 32];
+    for (chunk, s) in hash.chunks_exact_mut(4).zip(state) {
+        chunk.copy_from_slice(&s.to_be_bytes());
+    }
+    hash
+}
@@ -0,0 +1,53 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::{input_columns, InputImage, Tile, TilePixel, TilingConfig};
+use image::Rgb;
+
+/// Color of the tile borders drawn by the debug grid.
+const TILE_BORDER_COLOR: Rgb<u8> = Rgb([255, 0, 255]);
+/// Color of the columns where an input image starts or ends.
+const SEAM_COLOR: Rgb<u8> = Rgb([0, 255, 0]);
+
+/// Panorama columns of the left and right edges of each input image, gaps excluded.
+pub fn seam_columns(inputs: &[InputImage], config: &TilingConfig) -> Vec<usize> {
+    let mut columns = Vec::with_capacity(2 * inputs.len());
+    for slot in input_columns(inputs, config.gap) {
+        if !slot.is_empty() {
+            columns.push(slot.start);
+            columns.push(slot.end - 1);
+        }
+    }
+    columns.dedup();
+    columns
+}
+
+/// Draw the border of a tile of the column tx, and the seam columns it contains on top.
+pub fn draw_debug_grid<P: TilePixel>(
+    img: &mut Tile<P>,
+    tile_size: usize,
+    tx: usize,
+    seams: &[usize],
+) {
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        return;
+    }
+    let border_color = P::from_rgb(TILE_BORDER_COLOR);
+    for x in 0..width {
+        img.put_pixel(x, 0, border_color);
+        img.put_pixel(x, height - 1, border_color);
+    }
+    for y in 0..height {
+        img.put_pixel(0, y, border_color);
+        img.put_pixel(width - 1, y, border_color);
+    }
+    let left = tx * tile_size;
+    let seam_color = P::from_rgb(SEAM_COLOR);
+    for &column in seams {
+        if (left..left + width as usize).contains(&column) {
+            for y in 0..height {
+                img.put_pixel((column - left) as u32, y, seam_color);
+            }
+        }
+    }
+}
@@ -0,0 +1,61 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use std::convert::TryInto;
+
+/// Horizontal resolution of an encoded png or jpg image in pixels per inch,
+/// from its pHYs chunk or JFIF header. Return None if it is not recorded,
+/// in which case the image is assumed to have square pixels of unknown size.
+pub fn read_dpi(bytes: &[u8]) -> Option<f64> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        png_dpi(&bytes[8..])
+    } else if bytes.starts_with(&[0xff, 0xd8]) {
+        jpeg_dpi(&bytes[2..])
+    } else {
+        None
+    }
+}
+
+/// Pixels per meter of the pHYs chunk, which comes before the image data.
+fn png_dpi(mut chunks: &[u8]) -> Option<f64> {
+    while chunks.len() >= 8 {
+        let length = u32::from_be_bytes(chunks[0..4].try_into().ok()?) as usize;
+        let data = chunks.get(8..8 + length)?;
+        match &chunks[4..8] {
+            b"pHYs" if length == 9 => {
+                let pixels_per_unit = u32::from_be_bytes(data[0..4].try_into().ok()?);
+                // Unit 1 is the meter, 0 only gives the aspect ratio.
+                return match data[8] {
+                    1 if pixels_per_unit > 0 => Some(pixels_per_unit as f64 * 0.0254),
+                    _ => None,
+                };
+            }
+            b"IDAT" => return None,
+            _ => chunks = chunks.get(8 + length + 4..)?,
+        }
+    }
+    None
+}
+
+/// Density of the JFIF APP0 segment, which comes first.
+fn jpeg_dpi(mut segments: &[u8]) -> Option<f64> {
+    while segments.len() >= 4 && segments[0] == 0xff {
+        let marker = segments[1];
+        let length = u16::from_be_bytes([segments[2], segments[3]]) as usize;
+        let data = segments.get(4..2 + length)?;
+        match marker {
+            0xe0 if data.starts_with(b"JFIF\0") && data.len() >= 12 => {
+                let density = u16::from_be_bytes([data[8], data[9]]) as f64;
+                // Unit 1 is the inch, 2 the centimeter, and 0 only gives the aspect ratio.
+                return match data[7] {
+                    1 if density > 0.0 => Some(density),
+                    2 if density > 0.0 => Some(density * 2.54),
+                    _ => None,
+                };
+            }
+            // Start of the image data.
+            0xda => return None,
+            _ => segments = segments.get(2 + length..)?,
+        }
+    }
+    None
+}
@@ -0,0 +1,194 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::Context;
+use std::str::FromStr;
+
+/// Namespace of the DeepZoom xml files.
+pub const DEEPZOOM_NAMESPACE: &str = "http://schemas.microsoft.com/deepzoom/2008";
+
+/// Description of a tileset, as read from its .dzi file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DziInfo {
+    pub tile_size: usize,
+    pub overlap: usize,
+    pub format: String,
+    pub width: usize,
+    pub height: usize,
+    /// Url of the tiles directory relative to the .dzi file, if not `NAME_files/`.
+    pub url: Option<String>,
+}
+
+impl DziInfo {
+    /// Read the attributes of a .dzi file.
+    pub fn parse(xml: &str) -> anyhow::Result<Self> {
+        let number = |name| -> anyhow::Result<usize> {
+            attribute(xml, name)
+                .with_context(|| format!("Missing {} attribute", name))?
+                .parse()
+                .with_context(|| format!("Invalid {} attribute", name))
+        };
+        Ok(Self {
+            tile_size: number("TileSize")?,
+            overlap: number("Overlap")?,
+            format: attribute(xml, "Format")
+                .context("Missing Format attribute")?
+                .to_string(),
+            width: number("Width")?,
+            height: number("Height")?,
+            url: attribute(xml, "Url").map(unescape),
+        })
+    }
+
+    /// Content of the .dzi file, with optional regions of interest for the viewer.
+    pub fn to_xml(&self, display_rects: &[DisplayRect], max_level: usize) -> String {
+        let mut image = XmlElement::new("Image").attribute("xmlns", DEEPZOOM_NAMESPACE);
+        if let Some(url) = &self.url {
+            image = image.attribute("Url", url);
+        }
+        image = image
+            .attribute("TileSize", self.tile_size)
+            .attribute("Overlap", self.overlap)
+            .attribute("Format", &self.format)
+            .child(
+                XmlElement::new("Size")
+                    .attribute("Width", self.width)
+                    .attribute("Height", self.height),
+            );
+        if !display_rects.is_empty() {
+            let rects = display_rects.iter().map(|rect| rect.to_xml(max_level));
+            image = image.child(rects.fold(XmlElement::new("DisplayRects"), XmlElement::child));
+        }
+        image.to_document()
+    }
+}
+
+/// Region of interest of an image, in pixels of the full resolution level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayRect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl DisplayRect {
+    /// Rectangle shown at all levels, from 0 to max_level.
+    fn to_xml(self, max_level: usize) -> XmlElement {
+        let rect = XmlElement::new("Rect")
+            .attribute("X", self.x)
+            .attribute("Y", self.y)
+            .attribute("Width", self.width)
+            .attribute("Height", self.height);
+        XmlElement::new("DisplayRect")
+            .attribute("MinLevel", 0)
+            .attribute("MaxLevel", max_level)
+            .child(rect)
+    }
+}
+
+impl FromStr for DisplayRect {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values: Vec<usize> = s
+            .split(',')
+            .map(|value| value.trim().parse())
+            .collect::<Result<_, _>>()
+            .context("Expected X,Y,W,H")?;
+        match values[..] {
+            [x, y, width, height] if width > 0 && height > 0 => Ok(Self {
+                x,
+                y,
+                width,
+                height,
+            }),
+            [_, _, _, _] => anyhow::bail!("Expected a width and height > 0"),
+            _ => anyhow::bail!("Expected X,Y,W,H"),
+        }
+    }
+}
+
+/// Element of an xml document, with escaped attributes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XmlElement {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<XmlElement>,
+}
+
+impl XmlElement {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            attributes: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    pub fn attribute<V: ToString>(mut self, name: &str, value: V) -> Self {
+        self.attributes.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn child(mut self, child: XmlElement) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    /// Whole xml document, on a single line, with this element as root.
+    pub fn to_document(&self) -> String {
+        let mut xml = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+        self.write(&mut xml);
+        xml
+    }
+
+    fn write(&self, xml: &mut String) {
+        xml.push('<');
+        xml.push_str(&self.name);
+        for (name, value) in &self.attributes {
+            xml.push_str(&format!(" {}=\"{}\"", name, escape(value)));
+        }
+        if self.children.is_empty() {
+            xml.push_str("/>");
+        } else {
+            xml.push('>');
+            for child in &self.children {
+                child.write(xml);
+            }
+            xml.push_str(&format!("</{}>", self.name));
+        }
+    }
+}
+
+/// Escape the characters with a special meaning in xml attribute values.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Revert the escaping of [`escape`].
+fn unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Value of the first `name="value"` attribute of some xml.
+fn attribute<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let pattern = format!(" {}=\"", name);
+    let start = xml.find(&pattern)? + pattern.len();
+    let end = start + xml[start..].find('"')?;
+    Some(&xml[start..end])
+}
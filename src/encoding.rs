@@ -0,0 +1,172 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::{srgb, Tile, TilePixel};
+use anyhow::Context;
+use image::codecs::png::PngEncoder;
+use image::ColorType;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+/// Default quality of JPEG tiles.
+pub const DEFAULT_JPEG_QUALITY: u8 = 75;
+
+/// Lowest quality JPEG tiles are encoded with to fit in a size budget.
+pub const MIN_JPEG_QUALITY: u8 = 10;
+
+/// Chroma subsampling of JPEG tiles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChromaSubsampling {
+    /// Full resolution chroma.
+    Yuv444,
+    /// Chroma halved horizontally.
+    Yuv422,
+    /// Chroma halved horizontally and vertically.
+    Yuv420,
+}
+
+impl FromStr for ChromaSubsampling {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "4:4:4" => Ok(Self::Yuv444),
+            "4:2:2" => Ok(Self::Yuv422),
+            "4:2:0" => Ok(Self::Yuv420),
+            _ => anyhow::bail!("{} is not one of 4:4:4, 4:2:2 or 4:2:0", s),
+        }
+    }
+}
+
+/// How tiles are encoded into image files.
+#[derive(Clone, Debug)]
+pub struct TileEncoding {
+    /// Format of the tiles, which is also their file extension (jpg, png or avif).
+    pub format: String,
+    /// Quality of JPEG tiles, from 1 to 100.
+    pub jpeg_quality: u8,
+    pub jpeg_subsampling: ChromaSubsampling,
+    /// Encode progressive JPEG tiles instead of baseline ones.
+    pub jpeg_progressive: bool,
+    /// Tag jpg and png tiles as sRGB.
+    pub srgb_tag: bool,
+    /// Size budget of JPEG tiles in bytes, met by lowering their quality.
+    pub max_tile_bytes: Option<usize>,
+}
+
+impl TileEncoding {
+    /// Default encoding for the given format.
+    pub fn new(format: &str) -> Self {
+        Self {
+            format: format.to_string(),
+            jpeg_quality: DEFAULT_JPEG_QUALITY,
+            jpeg_subsampling: ChromaSubsampling::Yuv444,
+            jpeg_progressive: false,
+            srgb_tag: true,
+            max_tile_bytes: None,
+        }
+    }
+}
+
+/// Encode a tile into the bytes of an image file.
+pub fn encode_tile<P: TilePixel>(
+    img: &Tile<P>,
+    encoding: &TileEncoding,
+) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    match encoding.format.as_str() {
+        "jpg" => encode_jpeg(img, encoding, &mut bytes)?,
+        "png" => {
+            PngEncoder::new(&mut bytes).encode(
+                img.as_raw(),
+                img.width(),
+                img.height(),
+                P::COLOR_TYPE,
+            )?;
+            if encoding.srgb_tag {
+                srgb::insert_png_chunk(&mut bytes);
+            }
+        }
+        #[cfg(feature = "avif")]
+        "avif" => bytes = encode_avif(img)?,
+        format => anyhow::bail!("Unsupported tile format {}", format),
+    }
+    Ok(bytes)
+}
+
+/// Encode a tile, lowering the JPEG quality as little as possible to fit in the size budget.
+/// Quality is not lowered below [`MIN_JPEG_QUALITY`],
+/// so also return whether the tile fits in the budget.
+pub fn encode_tile_within_budget<P: TilePixel>(
+    img: &Tile<P>,
+    encoding: &TileEncoding,
+) -> anyhow::Result<(Vec<u8>, bool)> {
+    let bytes = encode_tile(img, encoding)?;
+    let max_bytes = match encoding.max_tile_bytes {
+        Some(max_bytes) if encoding.format == "jpg" && bytes.len() > max_bytes => max_bytes,
+        _ => return Ok((bytes, true)),
+    };
+    // Binary search of the highest quality fitting in the budget,
+    // keeping the smallest tile in case none fits.
+    let mut smallest = bytes;
+    let mut fitting = None;
+    let (mut low, mut high) = (MIN_JPEG_QUALITY, encoding.jpeg_quality);
+    while low < high {
+        let quality = low + (high - low) / 2;
+        let mut lower_encoding = encoding.clone();
+        lower_encoding.jpeg_quality = quality;
+        let bytes = encode_tile(img, &lower_encoding)?;
+        if bytes.len() <= max_bytes {
+            fitting = Some(bytes);
+            low = quality + 1;
+        } else {
+            if bytes.len() < smallest.len() {
+                smallest = bytes;
+            }
+            high = quality;
+        }
+    }
+    Ok(match fitting {
+        Some(bytes) => (bytes, true),
+        None => (smallest, false),
+    })
+}
+
+fn encode_jpeg<P: TilePixel>(
+    img: &Tile<P>,
+    encoding: &TileEncoding,
+    bytes: &mut Vec<u8>,
+) -> anyhow::Result<()> {
+    let color_type = match P::COLOR_TYPE {
+        ColorType::L8 => jpeg_encoder::ColorType::Luma,
+        ColorType::Rgb8 => jpeg_encoder::ColorType::Rgb,
+        color_type => anyhow::bail!("Unsupported jpg color type {:?}", color_type),
+    };
+    let mut encoder = jpeg_encoder::Encoder::new(bytes, encoding.jpeg_quality);
+    encoder.set_sampling_factor(match encoding.jpeg_subsampling {
+        ChromaSubsampling::Yuv444 => jpeg_encoder::SamplingFactor::R_4_4_4,
+        ChromaSubsampling::Yuv422 => jpeg_encoder::SamplingFactor::R_4_2_2,
+        ChromaSubsampling::Yuv420 => jpeg_encoder::SamplingFactor::R_4_2_0,
+    });
+    encoder.set_progressive(encoding.jpeg_progressive);
+    if encoding.srgb_tag {
+        encoder.add_icc_profile(srgb::icc_profile(P::CHANNEL_COUNT == 1))?;
+    }
+    let width = u16::try_from(img.width()).context("Tile too wide for jpg")?;
+    let height = u16::try_from(img.height()).context("Tile too high for jpg")?;
+    encoder.encode(img.as_raw(), width, height, color_type)?;
+    Ok(())
+}
+
+/// The image crate avif encoder is unusable so we call ravif directly.
+#[cfg(feature = "avif")]
+fn encode_avif<P: TilePixel>(img: &Tile<P>) -> anyhow::Result<Vec<u8>> {
+    let pixels: Vec<ravif::RGB8> = img
+        .pixels()
+        .map(|p| {
+            let [r, g, b] = p.to_rgb().0;
+            ravif::RGB8::new(r, g, b)
+        })
+        .collect();
+    let buffer = ravif::Img::new(&pixels[..], img.width() as usize, img.height() as usize);
+    let encoded = ravif::Encoder::new().encode_rgb(buffer)?;
+    Ok(encoded.avif_file)
+}
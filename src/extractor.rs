@@ -0,0 +1,300 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::stream::RowStream;
+use crate::{panorama_size, InputImage, Tile, TilePixel, TilingConfig};
+use image::imageops::{crop_imm, FilterType::Lanczos3};
+use image::io::Reader as ImageReader;
+#[cfg(feature = "mmap")]
+use image::ImageFormat;
+use image::{DynamicImage, GenericImage, GenericImageView};
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+#[cfg(feature = "mmap")]
+use std::io::Cursor;
+use std::path::Path;
+
+pub struct ImgExtractor<'a, P: TilePixel> {
+    inputs: &'a [InputImage],
+    full_width: usize,
+    full_height: usize,
+    background: P,
+    gap: usize,
+    gap_color: P,
+    feather: usize,
+    mmap: bool,
+    low_memory: bool,
+    print_tile_sources: bool,
+    /// Decoded images, ordered by id so that any iteration over them is reproducible.
+    img_cache: BTreeMap<usize, Tile<P>>,
+    /// Images decoded a few rows at a time, in low memory mode.
+    streams: BTreeMap<usize, RowStream<'a>>,
+}
+
+impl<'a, P: TilePixel> ImgExtractor<'a, P> {
+    pub fn new(inputs: &'a [InputImage], config: &TilingConfig) -> Self {
+        let (full_width, full_height) = panorama_size(inputs, config);
+        Self {
+            inputs,
+            full_width,
+            full_height,
+            background: P::from_rgb(config.background),
+            gap: config.gap,
+            gap_color: P::from_rgb(config.gap_color),
+            // Feathering only applies to seams of touching images.
+            feather: if config.gap == 0 { config.feather } else { 0 },
+            mmap: config.mmap,
+            low_memory: config.low_memory,
+            print_tile_sources: config.print_tile_sources,
+            img_cache: BTreeMap::default(),
+            streams: BTreeMap::default(),
+        }
+    }
+    pub fn extract(&mut self, tile_size: usize, tx: usize, ty: usize) -> anyhow::Result<Tile<P>> {
+        let left = tx * tile_size;
+        let top = ty * tile_size;
+        let right = (left + tile_size).min(self.full_width);
+        let bottom = (top + tile_size).min(self.full_height);
+
+        // Initialize an RgbImage of the correct size.
+        let tile_width = tile_size.min(right - left) as u32;
+        let tile_height = tile_size.min(bottom - top) as u32;
+        let mut img_tile = Tile::from_pixel(tile_width, tile_height, self.background);
+
+        // Identify the images that need to be loaded.
+        // Clear all images from the cache that are not in that list.
+        // Load images that are not loaded yet in the cache.
+        // Images are separated by gaps, which are painted without decoding anything.
+        // Images close enough to a feathered seam of the tile are also loaded.
+        let mut accum_left = 0;
+        let mut seams = Vec::new();
+        for (id, input) in self.inputs.iter().enumerate() {
+            let w = input.width;
+            // Empty images have nothing to decode.
+            let visible = w > 0 && input.height > 0;
+            if visible && self.is_streamed(input) && left < accum_left + w && accum_left < right {
+                if self.print_tile_sources {
+                    eprintln!("Streaming image {} for tile ({}, {})", id, tx, ty);
+                }
+                self.copy_streamed(&mut img_tile, id, accum_left, left, top)?;
+            } else if visible
+                && left < accum_left + w + self.feather
+                && accum_left < right + self.feather
+            {
+                if self.print_tile_sources {
+                    eprintln!("Using image {} for tile ({}, {})", id, tx, ty);
+                }
+                // load the image if not
+                let img: &Tile<P> = match self.img_cache.entry(id) {
+                    Entry::Occupied(o) => o.into_mut(),
+                    Entry::Vacant(v) => {
+                        // rotate and resize the image, remove the trimmed borders
+                        // and crop to the panorama height
+                        let mut img = input.rotation.apply(decode(input, self.mmap)?);
+                        if let Some((width, height)) = input.resize {
+                            img = img.resize_exact(width as u32, height as u32, Lanczos3);
+                        }
+                        let img = P::from_dynamic(img);
+                        let (x, y) = (input.trim.left as u32, input.trim.top as u32);
+                        let h = input.height as u32;
+                        let trimmed_width = img.width() - x - input.trim.right as u32;
+                        let content_width = trimmed_width.min((w - input.pad_left) as u32);
+                        let content = crop_imm(&img, x, y, content_width, h);
+                        if content_width as usize == w {
+                            v.insert(content.to_image())
+                        } else {
+                            // pad narrower images with the background color
+                            let mut padded = Tile::from_pixel(w as u32, h, self.background);
+                            padded.copy_from(&content, input.pad_left as u32, 0)?;
+                            v.insert(padded)
+                        }
+                    }
+                };
+                if accum_left >= right || left >= accum_left + w {
+                    // only needed for feathering
+                    accum_left += w;
+                    seams.push(accum_left);
+                    continue;
+                }
+                // copy the correct view, shifted down by the image vertical offset
+                let inner_left = (left as i64 - accum_left as i64).max(0) as u32;
+                let inner_right = (right - accum_left).min(w) as u32;
+                let inner_width = inner_right - inner_left;
+                let inner_top = (top as i64 - input.offset_y as i64).max(0);
+                let inner_bottom = (bottom as i64 - input.offset_y as i64).min(input.height as i64);
+                if inner_top < inner_bottom {
+                    let img_view = img.view(
+                        inner_left,
+                        inner_top as u32,
+                        inner_width,
+                        (inner_bottom - inner_top) as u32,
+                    );
+                    let tile_inner_left = (accum_left as i64 - left as i64).max(0) as u32;
+                    let tile_inner_top = (inner_top + input.offset_y as i64 - top as i64) as u32;
+                    img_tile.copy_from(&img_view, tile_inner_left, tile_inner_top)?;
+                }
+            } else {
+                // unload the image if it was loaded
+                self.img_cache.remove(&id);
+                self.streams.remove(&id);
+            }
+            accum_left += w;
+            seams.push(accum_left);
+            if self.gap > 0 && id + 1 < self.inputs.len() {
+                let gap_left = accum_left.max(left);
+                let gap_right = (accum_left + self.gap).min(right);
+                for x in gap_left..gap_right {
+                    for y in 0..tile_height {
+                        img_tile.put_pixel((x - left) as u32, y, self.gap_color);
+                    }
+                }
+                accum_left += self.gap;
+            }
+            if accum_left >= right + self.feather {
+                break;
+            }
+        }
+
+        // Blend the pixels of both images on each side of the seams.
+        if self.feather > 0 {
+            for (id, &seam) in seams.iter().enumerate().take(self.inputs.len() - 1) {
+                self.feather_seam(&mut img_tile, left, top, id, seam);
+            }
+        }
+
+        Ok(img_tile)
+    }
+
+    /// In low memory mode, png images are decoded row by row for each tile instead of being cached,
+    /// unless they must be decoded whole to be rotated, resized or feathered.
+    fn is_streamed(&self, input: &InputImage) -> bool {
+        self.low_memory && self.feather == 0 && input.unstreamable_reason().is_none()
+    }
+
+    /// Copy the part of a streamed image overlapping the tile,
+    /// the image starting at column `img_left` of the panorama.
+    /// Rows are only decoded down to the bottom of the tile, so that the tiles below
+    /// in the same column continue reading the image where this one stopped.
+    fn copy_streamed(
+        &mut self,
+        img_tile: &mut Tile<P>,
+        id: usize,
+        img_left: usize,
+        left: usize,
+        top: usize,
+    ) -> anyhow::Result<()> {
+        let input: &'a InputImage = &self.inputs[id];
+        let right = left + img_tile.width() as usize;
+        let bottom = top + img_tile.height() as usize;
+        // Rows of the tile covered by the image, shifted down by its vertical offset.
+        let first_y = (top as isize).max(input.offset_y);
+        let last_y = (bottom as isize).min(input.offset_y + input.height as isize);
+        if first_y >= last_y {
+            return Ok(());
+        }
+        let image_y = |y: isize| (y - input.offset_y) as usize + input.trim.top;
+        let rows = image_y(first_y)..image_y(last_y);
+
+        let stream = match self.streams.entry(id) {
+            Entry::Occupied(o) => o.into_mut(),
+            Entry::Vacant(v) => v.insert(RowStream::open(input)?),
+        };
+        // Tiles of a new column start again from the top of the image.
+        if rows.start < stream.next_row() {
+            *stream = RowStream::open(input)?;
+        }
+
+        // Columns of the tile covered by the image content, after its left padding.
+        let content_left = img_left + input.pad_left;
+        let trimmed_width = stream.width() - input.trim.left - input.trim.right;
+        let content_width = trimmed_width.min(input.width - input.pad_left);
+        let first_x = left.max(content_left);
+        let last_x = right.min(content_left + content_width);
+        if first_x >= last_x {
+            return Ok(());
+        }
+        let image_x = |x: usize| x - content_left + input.trim.left;
+        let strip: Tile<P> = stream.read(image_x(first_x)..image_x(last_x), rows)?;
+        let tile_y = (first_y - top as isize) as u32;
+        img_tile.copy_from(&strip, (first_x - left) as u32, tile_y)?;
+        Ok(())
+    }
+
+    /// Blend a band of `feather` columns centered on the seam between images `id` and `id + 1`
+    /// by linearly interpolating the pixels of the two images, extended past their borders.
+    fn feather_seam(
+        &self,
+        img_tile: &mut Tile<P>,
+        left: usize,
+        top: usize,
+        id: usize,
+        seam: usize,
+    ) {
+        let (left_img, right_img) = match (self.img_cache.get(&id), self.img_cache.get(&(id + 1))) {
+            (Some(left_img), Some(right_img)) => (left_img, right_img),
+            _ => return,
+        };
+        let band_left = seam.saturating_sub(self.feather / 2);
+        let band_right = band_left + self.feather;
+        let first_x = band_left.max(left);
+        let last_x = band_right.min(left + img_tile.width() as usize);
+        let sample = |img: &Tile<P>, offset_y: isize, x: u32, y: usize| {
+            let img_y = y as i64 - offset_y as i64;
+            if img_y >= 0 && img_y < img.height() as i64 {
+                *img.get_pixel(x, img_y as u32)
+            } else {
+                self.background
+            }
+        };
+        for x in first_x..last_x {
+            let t = ((x - band_left) as f32 + 0.5) / self.feather as f32;
+            let left_start = seam - left_img.width() as usize;
+            let left_x = (x.clamp(left_start, seam - 1) - left_start) as u32;
+            let right_x = (x.max(seam) - seam).min(right_img.width() as usize - 1) as u32;
+            for y in 0..img_tile.height() {
+                let left_pixel =
+                    sample(left_img, self.inputs[id].offset_y, left_x, top + y as usize);
+                let right_pixel = sample(
+                    right_img,
+                    self.inputs[id + 1].offset_y,
+                    right_x,
+                    top + y as usize,
+                );
+                let blended = left_pixel.map2(&right_pixel, |l, r| {
+                    ((1.0 - t) * l as f32 + t * r as f32).round() as u8
+                });
+                img_tile.put_pixel((x - left) as u32, y, blended);
+            }
+        }
+    }
+}
+
+/// Decode an input image, from memory if it was already loaded.
+fn decode(input: &InputImage, mmap: bool) -> anyhow::Result<DynamicImage> {
+    if let Some(bytes) = &input.bytes {
+        return Ok(image::load_from_memory(bytes)?);
+    }
+    if mmap {
+        return decode_mapped(&input.path);
+    }
+    Ok(ImageReader::open(&input.path)?.decode()?)
+}
+
+/// Decode an input image from a memory map of the file,
+/// letting the OS page the encoded data in and out instead of reading it all in memory.
+#[cfg(feature = "mmap")]
+fn decode_mapped(path: &Path) -> anyhow::Result<DynamicImage> {
+    let file = std::fs::File::open(path)?;
+    // Safety: input files must not be modified while tiles are generated.
+    let mapped = unsafe { memmap2::Mmap::map(&file)? };
+    let mut reader = ImageReader::new(Cursor::new(&mapped[..]));
+    match ImageFormat::from_path(path) {
+        Ok(format) => reader.set_format(format),
+        Err(_) => reader = reader.with_guessed_format()?,
+    }
+    Ok(reader.decode()?)
+}
+
+#[cfg(not(feature = "mmap"))]
+fn decode_mapped(_path: &Path) -> anyhow::Result<DynamicImage> {
+    anyhow::bail!("Memory mapping inputs requires building zoomtiler with the \"mmap\" feature")
+}
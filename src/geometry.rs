@@ -0,0 +1,153 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Level and tile coordinates of the DeepZoom layout, shared by the generator and the verifier
+//! so that other tools, like tile servers, can compute the same grid.
+
+use crate::{panorama_size, InputImage, TilingConfig};
+use std::ops::Range;
+
+/// Compute the number of DeepZoom levels for an image whose largest dimension is `max_dimension`.
+/// Level 0 is a single pixel and each level doubles the resolution of the previous one,
+/// so this is `ceil(log2(max_dimension)) + 1`.
+pub fn levels_for(max_dimension: usize) -> usize {
+    assert!(max_dimension > 0);
+    ceil_log_2(max_dimension) + 1
+}
+
+/// Index of the full resolution level of a panorama of the given size.
+/// DeepZoom numbers levels from a single pixel at level 0,
+/// so this only depends on the largest dimension in pixels.
+pub fn base_level(width: usize, height: usize) -> usize {
+    levels_for(width.max(height)) - 1
+}
+
+/// Width and height in pixels of a level of a panorama of the given full resolution size.
+/// Each level is half the size of the next one, rounded up.
+pub fn level_size(width: usize, height: usize, level: usize) -> (usize, usize) {
+    let base_level = base_level(width, height);
+    assert!(
+        level <= base_level,
+        "Level {} is past the base level",
+        level
+    );
+    let scale = 1 << (base_level - level);
+    (width.div_ceil(scale), height.div_ceil(scale))
+}
+
+/// Number of columns and rows of tiles of a level of the given size in pixels.
+pub fn tile_grid(level_width: usize, level_height: usize, tile_size: usize) -> (usize, usize) {
+    (
+        level_width.div_ceil(tile_size),
+        level_height.div_ceil(tile_size),
+    )
+}
+
+/// Columns and rows of pixels of a level covered by the tile (tx, ty),
+/// including the overlap with its neighbors.
+/// Tiles of the last column and row are smaller if the level is not a multiple of the tile size.
+pub fn tile_bounds(
+    level_width: usize,
+    level_height: usize,
+    tile_size: usize,
+    overlap: usize,
+    tx: usize,
+    ty: usize,
+) -> (Range<usize>, Range<usize>) {
+    let bounds = |t: usize, level_size: usize| {
+        let start = (t * tile_size).saturating_sub(overlap);
+        let end = ((t + 1) * tile_size + overlap).min(level_size);
+        start..end
+    };
+    (bounds(tx, level_width), bounds(ty, level_height))
+}
+
+/// Columns of the full resolution panorama covered by each input image, from left to right.
+/// Consecutive images are separated by the gap.
+pub fn input_columns(inputs: &[InputImage], gap: usize) -> Vec<Range<usize>> {
+    let mut left = 0;
+    let mut columns = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        columns.push(left..left + input.width);
+        left += input.width + gap;
+    }
+    columns
+}
+
+/// Part of an input image backing a tile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageSpan {
+    /// Index of the input image.
+    pub input: usize,
+    /// Columns and rows of the full resolution panorama.
+    pub columns: Range<usize>,
+    pub rows: Range<usize>,
+    /// The same columns and rows in the input image, once resized,
+    /// where trimmed borders are skipped.
+    /// Columns past the right of an image narrower than its width in the panorama
+    /// are filled with the background.
+    pub image_columns: Range<usize>,
+    pub image_rows: Range<usize>,
+}
+
+/// Parts of the input images backing the tile (tx, ty) of a level, at full resolution.
+/// Tiles of coarser levels are computed from all the pixels of the full resolution
+/// area they cover, and feathered seams also blend pixels just outside of that area.
+pub fn tile_sources(
+    inputs: &[InputImage],
+    config: &TilingConfig,
+    level: usize,
+    tx: usize,
+    ty: usize,
+) -> Vec<ImageSpan> {
+    let (width, height) = panorama_size(inputs, config);
+    let scale = 1 << (base_level(width, height) - level);
+    let (level_width, level_height) = level_size(width, height, level);
+    let (level_columns, level_rows) =
+        tile_bounds(level_width, level_height, config.tile_size, 0, tx, ty);
+    let columns = level_columns.start * scale..(level_columns.end * scale).min(width);
+    let rows = level_rows.start * scale..(level_rows.end * scale).min(height);
+
+    let mut spans = Vec::new();
+    for (id, (input, slot)) in inputs
+        .iter()
+        .zip(input_columns(inputs, config.gap))
+        .enumerate()
+    {
+        // Narrower images are centered in their slot, after pad_left columns of background.
+        let content_left = slot.start + input.pad_left;
+        let span_columns = columns.start.max(content_left)..columns.end.min(slot.end);
+        // Images are shifted down by their vertical offset.
+        let top = input.offset_y.max(0) as usize;
+        let bottom = (input.offset_y + input.height as isize).max(0) as usize;
+        let span_rows = rows.start.max(top)..rows.end.min(bottom);
+        if span_columns.is_empty() || span_rows.is_empty() {
+            continue;
+        }
+        let image_x = |x: usize| x - content_left + input.trim.left;
+        let image_y = |y: usize| (y as isize - input.offset_y) as usize + input.trim.top;
+        spans.push(ImageSpan {
+            input: id,
+            image_columns: image_x(span_columns.start)..image_x(span_columns.end),
+            image_rows: image_y(span_rows.start)..image_y(span_rows.end),
+            columns: span_columns,
+            rows: span_rows,
+        });
+    }
+    spans
+}
+
+fn ceil_log_2(x: usize) -> usize {
+    if x == 1 {
+        0
+    } else {
+        log_2(x - 1) + 1
+    }
+}
+
+fn log_2(x: usize) -> usize {
+    num_bits::<usize>() - x.leading_zeros() as usize - 1
+}
+
+const fn num_bits<T>() -> usize {
+    std::mem::size_of::<T>() * 8
+}
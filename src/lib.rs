@@ -0,0 +1,578 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Generate deepzoom zoomable tiled images from horizontally consecutive images.
+
+mod build_info;
+mod cache;
+mod checksums;
+mod debug_grid;
+mod dpi;
+mod dzi;
+mod encoding;
+mod extractor;
+mod geometry;
+mod limit;
+mod naming;
+mod order;
+mod pyramid;
+mod remote;
+mod serve;
+mod sink;
+mod srgb;
+mod stream;
+mod trim;
+mod verify;
+
+pub use build_info::{BuildInfo, Overview};
+pub use cache::{content_hash, BuildCache, CacheEntry};
+pub use checksums::{sha256_hex, ChecksumProblem, Checksums, CHECKSUMS_FILE};
+pub use dpi::read_dpi;
+pub use dzi::{DisplayRect, DziInfo, XmlElement, DEEPZOOM_NAMESPACE};
+pub use encoding::{
+    encode_tile, ChromaSubsampling, TileEncoding, DEFAULT_JPEG_QUALITY, MIN_JPEG_QUALITY,
+};
+pub use geometry::{
+    base_level, input_columns, level_size, levels_for, tile_bounds, tile_grid, tile_sources,
+    ImageSpan,
+};
+pub use limit::{default_max_open_files, OpenFileGuard, OpenFilesLimit};
+pub use naming::TileNameTemplate;
+pub use order::TileOrder;
+pub use pyramid::DownsampleMode;
+#[cfg(feature = "http")]
+pub use remote::download;
+pub use remote::is_url;
+pub use serve::{viewer_html, PreviewServer};
+pub use sink::{FsTileSink, MultiTileSink, SkipColorSink, TileSink};
+pub use trim::{detect_borders, Borders};
+pub use verify::{verify_tiles, TileProblem};
+
+use anyhow::Context;
+use extractor::ImgExtractor;
+use image::{DynamicImage, GenericImage, ImageBuffer, Luma, Pixel, Rgb};
+use rayon::prelude::*;
+use std::fmt;
+use std::ops::{Range, RangeInclusive};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// Pixel types that tiles can be generated with.
+pub trait TilePixel: Pixel<Subpixel = u8> + Send + 'static {
+    fn from_dynamic(img: DynamicImage) -> Tile<Self>;
+    fn from_rgb(color: Rgb<u8>) -> Self;
+}
+
+impl TilePixel for Rgb<u8> {
+    fn from_dynamic(img: DynamicImage) -> Tile<Self> {
+        img.into_rgb8()
+    }
+    fn from_rgb(color: Rgb<u8>) -> Self {
+        color
+    }
+}
+
+impl TilePixel for Luma<u8> {
+    fn from_dynamic(img: DynamicImage) -> Tile<Self> {
+        img.into_luma8()
+    }
+    fn from_rgb(color: Rgb<u8>) -> Self {
+        color.to_luma()
+    }
+}
+
+/// Image buffer of a single tile.
+pub type Tile<P> = ImageBuffer<P, Vec<u8>>;
+
+/// An input image of the panorama.
+pub struct InputImage {
+    pub path: PathBuf,
+    /// Width of the image in the panorama, once trimmed.
+    /// The image may be narrower, see `pad_left`.
+    pub width: usize,
+    /// Height of the image, once trimmed and cropped.
+    /// The panorama is as high as its highest image,
+    /// with the background color below shorter images.
+    pub height: usize,
+    /// Rotation applied to the decoded image, before resizing and trimming it.
+    pub rotation: Rotation,
+    /// Size the decoded image is resized to before trimming it, if its resolution is normalized.
+    /// Other sizes and borders are in pixels of the rotated and resized image.
+    pub resize: Option<(usize, usize)>,
+    /// Borders removed from the image before stitching it.
+    pub trim: Borders,
+    /// Vertical shift of the image content in pixels, positive values moving it down.
+    pub offset_y: isize,
+    /// Columns of background color left of images narrower than their `width`,
+    /// to center them in a common width. The remaining columns are right of them.
+    pub pad_left: usize,
+    /// Encoded content of the image if it is already in memory, like downloaded images.
+    /// It is decoded instead of reading the file at `path`.
+    pub bytes: Option<Vec<u8>>,
+}
+
+impl InputImage {
+    /// Why the rows of the image cannot be decoded a few at a time in low memory mode,
+    /// which decodes it whole instead, if they cannot.
+    pub fn unstreamable_reason(&self) -> Option<&'static str> {
+        if !stream::is_png(self) {
+            Some("it is not a png image")
+        } else if self.rotation != Rotation::None {
+            Some("it is rotated")
+        } else if self.resize.is_some() {
+            Some("it is resized")
+        } else {
+            None
+        }
+    }
+}
+
+/// Settings of the tiles generation.
+#[derive(Debug, Clone)]
+pub struct TilingConfig {
+    pub tile_size: usize,
+    /// Color of the areas not covered by any image.
+    pub background: Rgb<u8>,
+    /// Width of the columns inserted between consecutive images.
+    pub gap: usize,
+    pub gap_color: Rgb<u8>,
+    /// Width of the band blended across each seam between images, ignored if there is a gap.
+    pub feather: usize,
+    pub level_strategy: LevelStrategy,
+    /// How each level is reduced to the next coarser one.
+    pub downsample: DownsampleMode,
+    /// Only generate the full resolution level, for servers computing the other ones.
+    pub base_only: bool,
+    /// Memory map input files instead of reading them, needs the "mmap" feature.
+    pub mmap: bool,
+    /// Decode png inputs a few rows at a time for each tile, instead of keeping them decoded
+    /// whole, at the cost of decoding them again for each column of tiles.
+    pub low_memory: bool,
+    /// Delete the levels finer than this one once their parent level is built,
+    /// to only keep the coarsest levels.
+    pub discard_levels_above: Option<usize>,
+    /// Debugging lines drawn on the tiles, at tile borders and input seams.
+    pub debug_grid: DebugGrid,
+    /// Order in which the tiles of each level are generated.
+    pub tile_order: TileOrder,
+    /// Print the input images used by each generated tile to stderr.
+    pub print_tile_sources: bool,
+}
+
+/// Width and height of the tiles, unless configured otherwise.
+pub const DEFAULT_TILE_SIZE: usize = 512;
+
+/// Black background, white gaps and the default of every other setting,
+/// to only set the ones that differ with `..Default::default()`.
+impl Default for TilingConfig {
+    fn default() -> Self {
+        Self {
+            tile_size: DEFAULT_TILE_SIZE,
+            background: Rgb([0, 0, 0]),
+            gap: 0,
+            gap_color: Rgb([255, 255, 255]),
+            feather: 0,
+            level_strategy: LevelStrategy::default(),
+            downsample: DownsampleMode::default(),
+            base_only: false,
+            mmap: false,
+            low_memory: false,
+            discard_levels_above: None,
+            debug_grid: DebugGrid::default(),
+            tile_order: TileOrder::default(),
+            print_tile_sources: false,
+        }
+    }
+}
+
+/// Which levels show the debugging grid lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DebugGrid {
+    #[default]
+    Off,
+    /// Only the full resolution tiles, the other levels are built without the lines.
+    BaseLevel,
+    /// The full resolution tiles, with the lines downscaled in all the other levels.
+    AllLevels,
+}
+
+/// Clockwise rotation of an input image, for slices shot in another orientation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rotation {
+    #[default]
+    None,
+    Quarter,
+    Half,
+    ThreeQuarters,
+}
+
+impl Rotation {
+    /// Size of an image of the given size once rotated.
+    pub fn size(self, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Self::None | Self::Half => (width, height),
+            Self::Quarter | Self::ThreeQuarters => (height, width),
+        }
+    }
+
+    pub fn apply(self, img: DynamicImage) -> DynamicImage {
+        match self {
+            Self::None => img,
+            Self::Quarter => img.rotate90(),
+            Self::Half => img.rotate180(),
+            Self::ThreeQuarters => img.rotate270(),
+        }
+    }
+}
+
+impl fmt::Display for Rotation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let degrees = match self {
+            Self::None => 0,
+            Self::Quarter => 90,
+            Self::Half => 180,
+            Self::ThreeQuarters => 270,
+        };
+        write!(f, "{}", degrees)
+    }
+}
+
+impl FromStr for Rotation {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" => Ok(Self::None),
+            "90" => Ok(Self::Quarter),
+            "180" => Ok(Self::Half),
+            "270" => Ok(Self::ThreeQuarters),
+            _ => anyhow::bail!("{} is not a rotation, expected 0, 90, 180 or 270", s),
+        }
+    }
+}
+
+/// How the number of generated levels is chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LevelStrategy {
+    /// All levels down to a single pixel, as expected by DeepZoom viewers.
+    #[default]
+    MaxDim,
+    /// Levels until the tile grid of the longest dimension fits in a single tile.
+    TileCount,
+    /// Half way between the tile count levels of the width and of the height.
+    Average,
+}
+
+impl FromStr for LevelStrategy {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "max-dim" => Ok(Self::MaxDim),
+            "tile-count" => Ok(Self::TileCount),
+            "average" => Ok(Self::Average),
+            _ => anyhow::bail!(
+                "{} is not a level strategy, expected max-dim, tile-count or average",
+                s
+            ),
+        }
+    }
+}
+
+/// Width and height of the full panorama, gaps included.
+pub fn panorama_size(inputs: &[InputImage], config: &TilingConfig) -> (usize, usize) {
+    let images_width: usize = inputs.iter().map(|input| input.width).sum();
+    let gaps_width = config.gap * (inputs.len() - 1);
+    let height = inputs.iter().map(|input| input.height).max().unwrap();
+    (images_width + gaps_width, height)
+}
+
+/// Number of levels generated for a panorama of the given size,
+/// counting down from the base level.
+pub fn level_count(width: usize, height: usize, config: &TilingConfig) -> usize {
+    let (tile_count_width, tile_count_height) = tile_grid(width, height, config.tile_size);
+    let width_levels = levels_for(tile_count_width);
+    let height_levels = levels_for(tile_count_height);
+    match config.level_strategy {
+        LevelStrategy::MaxDim => base_level(width, height) + 1,
+        LevelStrategy::TileCount => width_levels.max(height_levels),
+        // Half way from the min and max, which correspond to the number of levels
+        // for the smallest and longest dimensions.
+        LevelStrategy::Average => (width_levels + height_levels).div_ceil(2),
+    }
+}
+
+/// Levels of a panorama of the given size left in the sink once its tiles are generated,
+/// from the coarsest one to the finest one not discarded.
+pub fn generated_levels(
+    width: usize,
+    height: usize,
+    config: &TilingConfig,
+) -> RangeInclusive<usize> {
+    let base_level = base_level(width, height);
+    let coarsest_level = if config.base_only {
+        base_level
+    } else {
+        base_level + 1 - level_count(width, height, config)
+    };
+    let finest_level = match config.discard_levels_above {
+        Some(keep) => keep.min(base_level).max(coarsest_level),
+        None => base_level,
+    };
+    coarsest_level..=finest_level
+}
+
+/// Largest width or height of a panorama in pixels,
+/// since image coordinates are `u32` in the image crate and in most viewers.
+pub const MAX_DIMENSION: usize = u32::MAX as usize;
+
+/// Largest number of tiles generated for a single panorama, all levels included.
+pub const MAX_TILE_COUNT: usize = 100_000_000;
+
+/// Total number of tiles generated for a panorama of the given size, all levels included.
+pub fn tile_count(width: usize, height: usize, config: &TilingConfig) -> usize {
+    let levels = if config.base_only {
+        1
+    } else {
+        level_count(width, height, config)
+    };
+    let base_level = base_level(width, height);
+    (0..levels)
+        .map(|k| {
+            let (level_width, level_height) = level_size(width, height, base_level - k);
+            let (columns, rows) = tile_grid(level_width, level_height, config.tile_size);
+            columns * rows
+        })
+        .sum()
+}
+
+/// Check that a panorama of the given size can be tiled,
+/// before starting a generation that would fail or never end.
+pub fn check_panorama_size(
+    width: usize,
+    height: usize,
+    config: &TilingConfig,
+) -> anyhow::Result<()> {
+    let tiles = tile_count(width, height, config);
+    if width > MAX_DIMENSION || height > MAX_DIMENSION || tiles > MAX_TILE_COUNT {
+        anyhow::bail!(
+            "The panorama of {}x{} pixels is too large: {} levels, a base grid of {}x{} tiles, \
+            {} tiles in total (at most {} pixels on each side and {} tiles are supported)",
+            width,
+            height,
+            level_count(width, height, config),
+            width.div_ceil(config.tile_size),
+            height.div_ceil(config.tile_size),
+            tiles,
+            MAX_DIMENSION,
+            MAX_TILE_COUNT
+        );
+    }
+    Ok(())
+}
+
+/// Generate the tiles of all levels into the sink, starting from the full resolution one.
+///
+/// With the default [`LevelStrategy::MaxDim`], levels go down to a single pixel
+/// as required by DeepZoom, even if the whole image already fits in one tile.
+/// Other strategies stop earlier, but keep the DeepZoom numbering of levels.
+///
+/// Columns of tiles are generated in parallel in the current rayon thread pool.
+/// Each thread keeps its own cache of decoded input images.
+pub fn generate_tiles<P: TilePixel, S: TileSink<P>>(
+    inputs: &[InputImage],
+    config: &TilingConfig,
+    sink: &S,
+) -> anyhow::Result<()> {
+    let (width_sum, height) = panorama_size(inputs, config);
+    let all_columns = 0..width_sum;
+    update_tiles(inputs, config, sink, &[all_columns], 0..height)
+}
+
+/// Assemble the full resolution panorama into a single image, without tiling it.
+///
+/// The image is filled one column of tiles after the other,
+/// so that only the input images overlapping the current column are kept decoded.
+pub fn stitch_panorama<P: TilePixel>(
+    inputs: &[InputImage],
+    config: &TilingConfig,
+) -> anyhow::Result<Tile<P>> {
+    let (width, height) = panorama_size(inputs, config);
+    let bytes = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(P::CHANNEL_COUNT as usize));
+    if width > MAX_DIMENSION
+        || height > MAX_DIMENSION
+        || bytes.is_none_or(|b| b > isize::MAX as usize)
+    {
+        anyhow::bail!(
+            "The panorama of {}x{} pixels is too large to be stitched into a single image",
+            width,
+            height
+        );
+    }
+    let tile_size = config.tile_size;
+    let (tile_count_width, tile_count_height) = tile_grid(width, height, tile_size);
+    let mut panorama = Tile::<P>::new(width as u32, height as u32);
+    let mut extractor = ImgExtractor::<P>::new(inputs, config);
+    for tx in 0..tile_count_width {
+        for ty in 0..tile_count_height {
+            let tile = extractor.extract(tile_size, tx, ty)?;
+            panorama.copy_from(&tile, (tx * tile_size) as u32, (ty * tile_size) as u32)?;
+        }
+    }
+    Ok(panorama)
+}
+
+/// Assemble an overview of the panorama no larger than `max_size` pixels on each side,
+/// from the tiles of the generated levels already in the sink.
+///
+/// This is the finest level that fits and is still in the sink. If the generated levels
+/// stop before one fits, the coarsest one is downsampled further.
+pub fn overview<P: TilePixel, S: TileSink<P>>(
+    sink: &S,
+    width: usize,
+    height: usize,
+    config: &TilingConfig,
+    max_size: usize,
+) -> anyhow::Result<Tile<P>> {
+    assert!(max_size > 0);
+    let levels = generated_levels(width, height, config);
+    let coarsest_level = *levels.start();
+    let fits = |level: &usize| {
+        let (level_width, level_height) = level_size(width, height, *level);
+        level_width <= max_size && level_height <= max_size
+    };
+    let level = levels.rev().find(fits).unwrap_or(coarsest_level);
+
+    let tile_size = config.tile_size;
+    let (level_width, level_height) = level_size(width, height, level);
+    let (tile_count_width, tile_count_height) = tile_grid(level_width, level_height, tile_size);
+    let mut img = Tile::<P>::new(level_width as u32, level_height as u32);
+    for tx in 0..tile_count_width {
+        for ty in 0..tile_count_height {
+            let tile = sink
+                .read_tile(level, tx, ty)?
+                .with_context(|| format!("Missing tile ({}, {}) of level {}", tx, ty, level))?;
+            img.copy_from(&tile, (tx * tile_size) as u32, (ty * tile_size) as u32)?;
+        }
+    }
+    while img.width() as usize > max_size || img.height() as usize > max_size {
+        let empty = Tile::default;
+        img = pyramid::half_res(img, empty(), empty(), empty(), config.downsample);
+    }
+    Ok(img)
+}
+
+/// Regenerate the tiles overlapping both the given ranges of panorama columns
+/// and the given range of rows (in pixels), and their ancestors in the coarser levels.
+/// The other tiles already in the sink are left untouched.
+pub fn update_tiles<P: TilePixel, S: TileSink<P>>(
+    inputs: &[InputImage],
+    config: &TilingConfig,
+    sink: &S,
+    changed_columns: &[Range<usize>],
+    changed_rows: Range<usize>,
+) -> anyhow::Result<()> {
+    let (width_sum, height) = panorama_size(inputs, config);
+    let tile_size = config.tile_size;
+
+    // Compute the tile grid at full resolution.
+    let (tile_count_width, tile_count_height) = tile_grid(width_sum, height, tile_size);
+    let base_level = base_level(width_sum, height);
+    let levels = level_count(width_sum, height, config);
+
+    // Columns of tiles overlapping a changed range.
+    let base_columns: Vec<usize> = (0..tile_count_width)
+        .filter(|tx| {
+            let (left, right) = (tx * tile_size, (tx + 1) * tile_size);
+            changed_columns
+                .iter()
+                .any(|range| range.start < right && left < range.end)
+        })
+        .collect();
+    // Rows of tiles overlapping the changed rows.
+    let base_rows =
+        changed_rows.start / tile_size..changed_rows.end.div_ceil(tile_size).min(tile_count_height);
+
+    // Start generating the images at the highest resolution level.
+    // TODO: when vertical panoramas inputs will be allowed,
+    // be careful with the image access order.
+    let seams = debug_grid::seam_columns(inputs, config);
+    let write_base_tile = |extractor: &mut ImgExtractor<P>, grid: bool, tx, ty| {
+        let mut img = extractor.extract(tile_size, tx, ty)?;
+        if grid {
+            debug_grid::draw_debug_grid(&mut img, tile_size, tx, &seams);
+        }
+        sink.write_tile(base_level, tx, ty, &img)
+    };
+    let write_base_tiles = |grid: bool| match config.tile_order {
+        TileOrder::Column => base_columns.par_iter().try_for_each_init(
+            || ImgExtractor::<P>::new(inputs, config),
+            |extractor, &tx| {
+                for ty in base_rows.clone() {
+                    write_base_tile(extractor, grid, tx, ty)?;
+                }
+                anyhow::Ok(())
+            },
+        ),
+        order => {
+            // Each thread keeps its extractor from one batch of tiles to the next.
+            let extractors: Vec<Mutex<ImgExtractor<P>>> = (0..rayon::current_num_threads())
+                .map(|_| Mutex::new(ImgExtractor::new(inputs, config)))
+                .collect();
+            let tiles = order.sort(&base_columns, base_rows.clone());
+            order::for_each_in_order(&tiles, |tx, ty| {
+                let thread = rayon::current_thread_index().unwrap_or(0);
+                let mut extractor = extractors[thread].lock().unwrap();
+                write_base_tile(&mut extractor, grid, tx, ty)
+            })
+        }
+    };
+    // Lines only drawn on the base level are drawn after building the pyramid,
+    // by extracting the base tiles a second time.
+    let grid_in_pyramid = config.base_only || config.debug_grid == DebugGrid::AllLevels;
+    write_base_tiles(config.debug_grid != DebugGrid::Off && grid_in_pyramid)?;
+
+    if config.base_only {
+        return Ok(());
+    }
+    let mut columns = base_columns.clone();
+    let mut rows = base_rows.clone();
+
+    // Now, we need to take 2x2 blocs of images
+    // and complete the pyramid of levels by halfing the resolution each time.
+    let mut parent_x_tiles = tile_count_width;
+    let mut parent_y_tiles = tile_count_height;
+    let coarsest_level = base_level + 1 - levels;
+    for parent_level in (coarsest_level + 1..=base_level).rev() {
+        // A tile changes if one of its 2x2 children changed.
+        columns = columns.iter().map(|tx| tx / 2).collect();
+        columns.dedup();
+        rows = rows.start / 2..rows.end.div_ceil(2);
+        let (child_x_tiles, child_y_tiles) = pyramid::compute_half_resolutions(
+            sink,
+            parent_level,
+            parent_x_tiles,
+            parent_y_tiles,
+            &columns,
+            rows.clone(),
+            config,
+        )?;
+        parent_x_tiles = child_x_tiles;
+        parent_y_tiles = child_y_tiles;
+        // The level is only needed to build its parent, which is now complete.
+        if config
+            .discard_levels_above
+            .is_some_and(|keep| parent_level > keep)
+        {
+            sink.discard_level(parent_level)?;
+        }
+    }
+
+    let base_discarded = config
+        .discard_levels_above
+        .is_some_and(|keep| base_level > keep);
+    if config.debug_grid == DebugGrid::BaseLevel && !base_discarded {
+        write_base_tiles(true)?;
+    }
+    Ok(())
+}
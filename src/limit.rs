@@ -0,0 +1,77 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use std::sync::{Condvar, Mutex};
+
+/// Number of files a tile sink may keep open at once when the platform limit is unknown.
+const FALLBACK_MAX_OPEN_FILES: usize = 256;
+
+/// Semaphore bounding the number of tile files opened at the same time,
+/// whatever the number of threads generating them.
+pub struct OpenFilesLimit {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+impl OpenFilesLimit {
+    pub fn new(max_open_files: usize) -> Self {
+        assert!(max_open_files > 0, "At least one open file is needed");
+        Self {
+            available: Mutex::new(max_open_files),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Wait until a file can be opened.
+    /// It counts as open until the returned guard is dropped.
+    pub fn acquire(&self) -> OpenFileGuard<'_> {
+        let mut available = self.available.lock().unwrap();
+        while *available == 0 {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= 1;
+        OpenFileGuard { limit: self }
+    }
+}
+
+/// A file counted as open by an [`OpenFilesLimit`].
+pub struct OpenFileGuard<'a> {
+    limit: &'a OpenFilesLimit,
+}
+
+impl Drop for OpenFileGuard<'_> {
+    fn drop(&mut self) {
+        *self.limit.available.lock().unwrap() += 1;
+        self.limit.released.notify_one();
+    }
+}
+
+/// Default number of tile files opened at once, half of the limit of open files
+/// of the process to keep room for inputs and other files.
+pub fn default_max_open_files() -> usize {
+    platform_max_open_files()
+        .map(|limit| (limit / 2).max(1))
+        .unwrap_or(FALLBACK_MAX_OPEN_FILES)
+}
+
+/// Soft limit of open file descriptors of the process, if there is one.
+#[cfg(unix)]
+fn platform_max_open_files() -> Option<usize> {
+    use std::convert::TryFrom;
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: getrlimit only writes to the given rlimit struct.
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return None;
+    }
+    if limit.rlim_cur == libc::RLIM_INFINITY {
+        return None;
+    }
+    usize::try_from(limit.rlim_cur).ok()
+}
+
+#[cfg(not(unix))]
+fn platform_max_open_files() -> Option<usize> {
+    None
+}
@@ -1,18 +1,37 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use anyhow::Context;
-use image::imageops::crop_imm;
-use image::io::Reader as ImageReader;
-use image::{GenericImage, GenericImageView, Rgb, RgbImage};
+use image::imageops::FilterType::Lanczos3;
+use image::{Luma, Rgb};
 use seahorse::{Flag, FlagType};
-use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
 use std::ffi::OsStr;
-use std::path::{Path, PathBuf};
+use std::io::Read;
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use zoomtiler::{
+    base_level, check_panorama_size, content_hash, detect_borders, encode_tile, generated_levels,
+    level_count, overview, panorama_size, stitch_panorama, update_tiles, verify_tiles, Borders,
+    BuildCache, BuildInfo, CacheEntry, Checksums, DebugGrid, DisplayRect, DownsampleMode, DziInfo,
+    FsTileSink, InputImage, LevelStrategy, MultiTileSink, OpenFilesLimit, Overview, PreviewServer,
+    Rotation, SkipColorSink, TileEncoding, TileNameTemplate, TileOrder, TileProblem, TileSink,
+    TilingConfig, XmlElement, CHECKSUMS_FILE, DEEPZOOM_NAMESPACE,
+};
 
 const DEFAULT_OUTPUT: &str = "tiles/tiles.dzi";
+const DEFAULT_COLLECTION_OUTPUT: &str = "tiles/collection.dzc";
 const DEFAULT_FORMAT: &str = "jpg";
-const DEFAULT_TILE_SIZE: isize = 512;
+const DEFAULT_TILE_SIZE: isize = zoomtiler::DEFAULT_TILE_SIZE as isize;
+const DEFAULT_BACKGROUND: &str = "000000";
+const DEFAULT_GAP_COLOR: &str = "ffffff";
+const DEFAULT_PORT: isize = 8000;
+const DEFAULT_FILES_SUFFIX: &str = "_files";
+const DEFAULT_IO_RETRIES: isize = 2;
+const OVERVIEW_FILE: &str = "overview.jpg";
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
@@ -25,49 +44,837 @@ fn main() {
         .flag(
             Flag::new("output", FlagType::String)
                 .description(format!("{} *.png --output {}", app_name, DEFAULT_OUTPUT)),
+        );
+    let app = tiling_flags(app_name)
+        .into_iter()
+        .fold(app, |app, flag| app.flag(flag))
+        .flag(
+            Flag::new("offsets", FlagType::String)
+                .description(format!("{} 1.png 2.png 3.png --offsets 0,-12,5", app_name)),
+        )
+        .flag(
+            Flag::new("rotate", FlagType::String)
+                .description(format!("{} 1.png 2.png 3.png --rotate 0,90,0", app_name)),
+        )
+        .flag(Flag::new("heights", FlagType::String).description(format!(
+            "{} 1.png 2.png 3.png --heights 600,580,600",
+            app_name
+        )))
+        .flag(Flag::new("stdout-dzi", FlagType::Bool).description(format!(
+            "{} *.png --stdout-dzi (print the .dzi file without generating tiles)",
+            app_name
+        )))
+        .flag(
+            Flag::new("stitch-only", FlagType::String).description(format!(
+                "{} *.png --stitch-only panorama.jpg (save a single image without tiling it)",
+                app_name
+            )),
+        )
+        .flag(
+            Flag::new("display-rect", FlagType::String).description(format!(
+                "{} *.png --display-rect 0,0,800,600 (regions of interest, separated by ;)",
+                app_name
+            )),
+        )
+        .flag(
+            Flag::new("only-rows", FlagType::String).description(format!(
+                "{} *.png --only-rows 3..5 (only regenerate these rows of base tiles)",
+                app_name
+            )),
+        )
+        .flag(
+            Flag::new("variants", FlagType::String)
+                .description(format!("{} *.png --variants hi:90,lo:40", app_name)),
         )
+        .flag(Flag::new("batch", FlagType::String).description(format!(
+            "{} --batch scans/ (tile each subdirectory as a panorama, in OUTPUT_DIR/SUBDIR)",
+            app_name
+        )))
         .flag(
-            Flag::new("format", FlagType::String)
-                .description(format!("{} *.png --format {}", app_name, DEFAULT_FORMAT)),
+            Flag::new("fail-fast", FlagType::Bool)
+                .description(format!("{} --batch scans/ --fail-fast", app_name)),
         )
-        .flag(Flag::new("tilesize", FlagType::Int).description(format!(
+        .action(|c| exit_on_error(run(c)))
+        .command(collection_command(app_name))
+        .command(verify_command(app_name))
+        .command(serve_command(app_name));
+    app.run(args);
+}
+
+/// Flags shared by all the commands generating tiles.
+fn tiling_flags(app_name: &str) -> Vec<Flag> {
+    vec![
+        Flag::new("format", FlagType::String)
+            .description(format!("{} *.png --format {}", app_name, DEFAULT_FORMAT)),
+        Flag::new("quality", FlagType::Int).description(format!(
+            "{} *.png --quality {}",
+            app_name,
+            zoomtiler::DEFAULT_JPEG_QUALITY
+        )),
+        Flag::new("jpeg-subsampling", FlagType::String).description(format!(
+            "{} *.png --jpeg-subsampling 4:4:4 (or 4:2:2, 4:2:0)",
+            app_name
+        )),
+        Flag::new("max-tile-bytes", FlagType::Int).description(format!(
+            "{} *.png --max-tile-bytes 60000 (lower the quality of larger jpg tiles)",
+            app_name
+        )),
+        Flag::new("progressive", FlagType::Bool).description(format!(
+            "{} *.png --progressive (progressive instead of baseline jpg)",
+            app_name
+        )),
+        Flag::new("no-srgb-tag", FlagType::Bool).description(format!(
+            "{} *.png --no-srgb-tag (do not tag jpg and png tiles as sRGB)",
+            app_name
+        )),
+        Flag::new("tilesize", FlagType::Int).description(format!(
             "{} *.png --tilesize {}",
             app_name, DEFAULT_TILE_SIZE
+        )),
+        Flag::new("grayscale", FlagType::Bool)
+            .description(format!("{} *.png --grayscale", app_name)),
+        Flag::new("background", FlagType::String).description(format!(
+            "{} *.png --background {}",
+            app_name, DEFAULT_BACKGROUND
+        )),
+        Flag::new("gap", FlagType::Int).description(format!(
+            "{} *.png --gap 4 (pixels between images)",
+            app_name
+        )),
+        Flag::new("gap-color", FlagType::String).description(format!(
+            "{} *.png --gap 4 --gap-color {}",
+            app_name, DEFAULT_GAP_COLOR
+        )),
+        Flag::new("normalize-dpi", FlagType::Float).description(format!(
+            "{} *.png --normalize-dpi 300 (resize inputs to the same resolution)",
+            app_name
+        )),
+        Flag::new("common-width", FlagType::Int).description(format!(
+            "{} *.png --common-width 600 (center crop or pad each image)",
+            app_name
+        )),
+        Flag::new("tile-name-template", FlagType::String).description(format!(
+            "{} *.png --tile-name-template tile-{{x:03}}-{{y:03}}.{{ext}} (default {{x}}_{{y}}.{{ext}})",
+            app_name
+        )),
+        Flag::new("files-suffix", FlagType::String).description(format!(
+            "{} *.png --files-suffix .dzi_files (tiles directory NAME.dzi_files, or an absolute path)",
+            app_name
+        )),
+        Flag::new("skip-color", FlagType::String).description(format!(
+            "{} *.png --skip-color ffffff (do not write tiles entirely of this color)",
+            app_name
+        )),
+        Flag::new("skip-tolerance", FlagType::Int).description(format!(
+            "{} *.png --skip-color ffffff --skip-tolerance 8",
+            app_name
+        )),
+        Flag::new("checksums", FlagType::Bool).description(format!(
+            "{} *.png --checksums (SHA-256 of the tiles in {})",
+            app_name, CHECKSUMS_FILE
+        )),
+        Flag::new("autotrim", FlagType::Bool).description(format!(
+            "{} *.png --autotrim (remove uniform borders of inputs)",
+            app_name
+        )),
+        Flag::new("trim-edges", FlagType::String).description(format!(
+            "{} *.png --trim-edges 1,1 (columns cropped left and right, or 1,0;1,1;0,1 per input)",
+            app_name
+        )),
+        Flag::new("trim-tolerance", FlagType::Int).description(format!(
+            "{} *.png --autotrim --trim-tolerance 8 (max difference of border pixels)",
+            app_name
+        )),
+        Flag::new("feather", FlagType::Int).description(format!(
+            "{} *.png --feather 32 (pixels blended across seams)",
+            app_name
+        )),
+        Flag::new("level-strategy", FlagType::String).description(format!(
+            "{} *.png --level-strategy max-dim (or tile-count, average)",
+            app_name
+        )),
+        Flag::new("downsample-mode", FlagType::String).description(format!(
+            "{} *.png --downsample-mode darkest (or mean, median, lightest)",
+            app_name
+        )),
+        Flag::new("tile-order", FlagType::String).description(format!(
+            "{} *.png --tile-order center-out (or column, row, spiral)",
+            app_name
+        )),
+        Flag::new("overview", FlagType::Int).description(format!(
+            "{} *.png --overview 256 (also save {}, at most 256x256)",
+            app_name, OVERVIEW_FILE
+        )),
+        Flag::new("base-only", FlagType::Bool).description(format!(
+            "{} *.png --base-only (only the full resolution tiles)",
+            app_name
+        )),
+        Flag::new("discard-levels", FlagType::Int).description(format!(
+            "{} *.png --discard-levels 8 (delete levels above 8 once used)",
+            app_name
+        )),
+        Flag::new("debug-grid", FlagType::Bool).description(format!(
+            "{} *.png --debug-grid (draw tile borders and image seams on full resolution tiles)",
+            app_name
+        )),
+        Flag::new("debug-grid-all-levels", FlagType::Bool).description(format!(
+            "{} *.png --debug-grid-all-levels (also keep the debug grid in the other levels)",
+            app_name
+        )),
+        Flag::new("mmap", FlagType::Bool).description(format!(
+            "{} huge.png --mmap (memory map inputs, needs the mmap feature)",
+            app_name
+        )),
+        Flag::new("low-memory", FlagType::Bool).description(format!(
+            "{} tall*.png --low-memory (decode png inputs a few rows at a time)",
+            app_name
+        )),
+        Flag::new("threads", FlagType::Int).description(format!(
+            "{} *.png --threads 4 (defaults to all cores)",
+            app_name
+        )),
+        Flag::new("max-open-files", FlagType::Int).description(format!(
+            "{} *.png --max-open-files 64 (defaults to half the open files limit)",
+            app_name
+        )),
+        Flag::new("io-retries", FlagType::Int).description(format!(
+            "{} *.png --io-retries {} (try again failed tile writes, with exponential backoff)",
+            app_name, DEFAULT_IO_RETRIES
+        )),
+        Flag::new("allow-network", FlagType::Bool).description(format!(
+            "{} https://example.com/1.png --allow-network (download URL inputs)",
+            app_name
+        )),
+        Flag::new("force", FlagType::Bool).description(format!(
+            "{} *.png --force (regenerate all tiles, not only the changed ones)",
+            app_name
+        )),
+        Flag::new("quiet", FlagType::Bool).description(format!(
+            "{} *.png --quiet (no progress messages or summary of the generated tiles)",
+            app_name
+        )),
+        Flag::new("verbose", FlagType::Bool).description(format!(
+            "{} *.png --verbose (print the order of the inputs)",
+            app_name
+        )),
+        Flag::new("sort", FlagType::String).description(format!(
+            "{} *.png --sort natural (order the inputs, img2 before img10, or lexical, none)",
+            app_name
+        )),
+        Flag::new("strict", FlagType::Bool).description(format!(
+            "{} *.png --strict (fail if image heights differ)",
+            app_name
+        )),
+        Flag::new("height-tolerance", FlagType::String).description(format!(
+            "{} *.png --strict --height-tolerance 10 (pixels, or 2% of the height)",
+            app_name
+        )),
+        Flag::new("reference-height-from", FlagType::String).description(format!(
+            "{} *.png --reference-height-from 3.png (or its index, instead of the smallest height)",
+            app_name
+        )),
+    ]
+}
+
+fn collection_command(app_name: &str) -> seahorse::Command {
+    let command = seahorse::Command::new("collection")
+        .description("Tile multiple panoramas and reference them in a DeepZoom collection")
+        .usage(format!(
+            "{} collection --panorama A1.png A2.png --panorama B1.png B2.png",
+            app_name
+        ))
+        .flag(Flag::new("output", FlagType::String).description(format!(
+            "{} collection --output {} --panorama *.png",
+            app_name, DEFAULT_COLLECTION_OUTPUT
+        )));
+    tiling_flags(app_name)
+        .into_iter()
+        .fold(command, |command, flag| command.flag(flag))
+        .action(|c| exit_on_error(run_collection(c)))
+}
+
+fn verify_command(app_name: &str) -> seahorse::Command {
+    seahorse::Command::new("verify")
+        .description("Check that all the tiles of a .dzi exist and decode with the correct size")
+        .usage(format!("{} verify tiles/tiles.dzi", app_name))
+        .flag(
+            Flag::new("tile-name-template", FlagType::String).description(format!(
+                "{} verify tiles/tiles.dzi --tile-name-template {{x}}_{{y}}.{{ext}}",
+                app_name
+            )),
+        )
+        .flag(Flag::new("checksums", FlagType::Bool).description(format!(
+            "{} verify tiles/tiles.dzi --checksums (also compare tiles with {})",
+            app_name, CHECKSUMS_FILE
         )))
-        .action(|c| {
-            run(c).unwrap_or_else(|err| {
-                eprintln!("{}", err);
-                panic!("There was a problem")
-            })
-        });
-    app.run(args);
+        .action(|c| exit_on_error(run_verify(c)))
 }
 
-/// Image output path with deepzoom convention.
-fn img_out_path(dir: &Path, extension: &str, tx: usize, ty: usize) -> PathBuf {
-    dir.join(format!("{}_{}.{}", tx, ty, extension))
+fn serve_command(app_name: &str) -> seahorse::Command {
+    seahorse::Command::new("serve")
+        .description("Preview a tileset in the browser, served over HTTP on localhost")
+        .usage(format!("{} serve tiles/tiles.dzi", app_name))
+        .flag(Flag::new("port", FlagType::Int).description(format!(
+            "{} serve tiles/tiles.dzi --port {}",
+            app_name, DEFAULT_PORT
+        )))
+        .action(|c| exit_on_error(run_serve(c)))
 }
 
-fn run(c: &seahorse::Context) -> anyhow::Result<()> {
-    let img_paths: Vec<&Path> = c.args.iter().map(Path::new).collect();
-    if img_paths.is_empty() {
-        anyhow::bail!("At least one input image is needed");
+fn exit_on_error(result: anyhow::Result<()>) {
+    result.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        panic!("There was a problem")
+    })
+}
+
+/// Tiling settings shared by all the panoramas of a run.
+struct Settings {
+    encoding: TileEncoding,
+    grayscale: bool,
+    config: TilingConfig,
+    /// Height difference above which images are rejected, if strict.
+    strict_height_tolerance: Option<HeightTolerance>,
+    /// Path or index of the input image giving the panorama height, instead of the smallest one.
+    reference_height_from: Option<String>,
+    /// Allow downloading input images given as URLs.
+    allow_network: bool,
+    /// Regenerate all tiles, even those of unchanged inputs.
+    force: bool,
+    /// Fail on invalid inputs instead of skipping them.
+    strict: bool,
+    /// Do not print progress messages or a summary of the generated tiles.
+    quiet: bool,
+    /// Print the order in which inputs are stitched.
+    verbose: bool,
+    /// How inputs are ordered from left to right.
+    input_order: InputOrder,
+    /// Only print the .dzi file to stdout, without generating tiles.
+    stdout_dzi: bool,
+    /// Only save the full resolution panorama as a single image, without generating tiles.
+    stitch_only: Option<PathBuf>,
+    /// Rows of base tiles to regenerate, instead of the rows of changed inputs.
+    only_rows: Option<Range<usize>>,
+    /// Resolution in dpi each image is resized to, if given.
+    normalize_dpi: Option<f64>,
+    /// Width each image is center cropped or padded to, if given.
+    common_width: Option<usize>,
+    /// Tolerance of the uniform borders trimmed from inputs, if trimming.
+    trim_tolerance: Option<u8>,
+    /// Columns cropped from the left and right of all inputs, or of each input.
+    trim_edges: Vec<(usize, usize)>,
+    /// Number of tile files opened at once, by all threads.
+    max_open_files: usize,
+    /// Number of times a failed tile write is tried again.
+    io_retries: usize,
+    /// Names of the tile files in each level directory.
+    tile_names: TileNameTemplate,
+    /// Color and tolerance of the tiles that are not written, if any.
+    skip_color: Option<(Rgb<u8>, u8)>,
+    /// Largest width and height of the overview saved in the output directory, if any.
+    overview: Option<usize>,
+    /// Save the hashes of the tiles in the checksums file of the output directory.
+    checksums: bool,
+    /// Suffix of the tiles directory appended to the .dzi name, or absolute tiles directory.
+    files_suffix: String,
+    /// Jpg qualities of the tiles variants, if more than one should be generated.
+    variants: Vec<Variant>,
+    /// Values of the flags changing the tiles, recorded for the next build.
+    flags: BTreeMap<String, String>,
+}
+
+/// Flags given on the command line, or else recorded by the build being redone.
+/// The values of those read are collected, to record them for the next build.
+struct Flags<'a> {
+    c: &'a seahorse::Context,
+    previous: Option<&'a BuildInfo>,
+    recorded: BTreeMap<String, String>,
+}
+
+impl<'a> Flags<'a> {
+    fn new(c: &'a seahorse::Context, previous: Option<&'a BuildInfo>) -> Self {
+        Self {
+            c,
+            previous,
+            recorded: BTreeMap::new(),
+        }
+    }
+
+    fn value<T: FromStr + ToString>(
+        &mut self,
+        name: &str,
+        given: Option<T>,
+    ) -> anyhow::Result<Option<T>>
+    where
+        anyhow::Error: From<T::Err>,
+    {
+        let recorded = self.previous.and_then(|p| p.settings.get(name));
+        let value = match (given, recorded) {
+            (Some(value), _) => Some(value),
+            (None, Some(recorded)) => Some(
+                recorded
+                    .parse()
+                    .map_err(anyhow::Error::from)
+                    .with_context(|| {
+                        format!("Invalid --{} recorded by the previous build", name)
+                    })?,
+            ),
+            (None, None) => None,
+        };
+        if let Some(value) = &value {
+            self.recorded.insert(name.to_string(), value.to_string());
+        }
+        Ok(value)
+    }
+
+    fn string(&mut self, name: &str) -> anyhow::Result<Option<String>> {
+        let given = self.c.string_flag(name).ok();
+        self.value(name, given)
+    }
+
+    fn int(&mut self, name: &str) -> anyhow::Result<Option<isize>> {
+        let given = self.c.int_flag(name).ok();
+        self.value(name, given)
+    }
+
+    fn float(&mut self, name: &str) -> anyhow::Result<Option<f64>> {
+        let given = self.c.float_flag(name).ok();
+        self.value(name, given)
+    }
+
+    /// Boolean flags recorded by the build being redone stay set.
+    fn bool(&mut self, name: &str) -> anyhow::Result<bool> {
+        let given = Some(true).filter(|_| self.c.bool_flag(name));
+        Ok(self.value(name, given)?.unwrap_or(false))
+    }
+}
+
+/// Tiles generated with a given jpg quality, in `NAME_variant.dzi`.
+#[derive(Debug)]
+struct Variant {
+    name: String,
+    quality: u8,
+}
+
+impl FromStr for Variant {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, quality) = s.split_once(':').context("Expected name:quality")?;
+        let quality: u8 = quality.parse()?;
+        if name.is_empty() || !(1..=100).contains(&quality) {
+            anyhow::bail!("Expected a name and a quality between 1 and 100");
+        }
+        Ok(Self {
+            name: name.to_string(),
+            quality,
+        })
+    }
+}
+
+/// Allowed difference between the height of an image and the panorama height.
+#[derive(Clone, Copy)]
+enum HeightTolerance {
+    Pixels(usize),
+    Percent(f64),
+}
+
+impl FromStr for HeightTolerance {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_suffix('%') {
+            Some(percent) => Ok(Self::Percent(percent.parse()?)),
+            None => Ok(Self::Pixels(s.parse()?)),
+        }
+    }
+}
+
+impl HeightTolerance {
+    fn allows(self, height: usize, panorama_height: usize) -> bool {
+        let difference = height.abs_diff(panorama_height);
+        match self {
+            Self::Pixels(pixels) => difference <= pixels,
+            Self::Percent(percent) => difference as f64 <= percent / 100.0 * panorama_height as f64,
+        }
+    }
+}
+
+/// How input images are ordered from left to right in the panorama.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum InputOrder {
+    /// The order of the arguments.
+    #[default]
+    None,
+    /// Paths compared character by character, so img10 comes before img2.
+    Lexical,
+    /// Numbers in paths compared by value, so img2 comes before img10.
+    Natural,
+}
+
+impl FromStr for InputOrder {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "lexical" => Ok(Self::Lexical),
+            "natural" => Ok(Self::Natural),
+            _ => anyhow::bail!("{} is not an order, expected natural, lexical or none", s),
+        }
+    }
+}
+
+impl InputOrder {
+    /// Indices of the paths in this order. Equal paths keep their order.
+    fn sort(self, paths: &[&Path]) -> Vec<usize> {
+        let mut indices: Vec<usize> = (0..paths.len()).collect();
+        match self {
+            Self::None => (),
+            Self::Lexical => indices
+                .sort_by(|&a, &b| paths[a].to_string_lossy().cmp(&paths[b].to_string_lossy())),
+            Self::Natural => indices.sort_by(|&a, &b| {
+                natural_cmp(&paths[a].to_string_lossy(), &paths[b].to_string_lossy())
+            }),
+        }
+        indices
+    }
+}
+
+/// Compare strings with their runs of digits compared by value, so img2 comes before img10.
+/// Equal numbers with more leading zeros come after, to keep a total order.
+fn natural_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (a_digits, b_digits) = (leading_digits(a), leading_digits(b));
+        let ordering = if !a_digits.is_empty() && !b_digits.is_empty() {
+            let (a_number, b_number) = (
+                a_digits.trim_start_matches('0'),
+                b_digits.trim_start_matches('0'),
+            );
+            let ordering = a_number
+                .len()
+                .cmp(&b_number.len())
+                .then(a_number.cmp(b_number))
+                .then(a_digits.len().cmp(&b_digits.len()));
+            a = &a[a_digits.len()..];
+            b = &b[b_digits.len()..];
+            ordering
+        } else {
+            let mut a_chars = a.chars();
+            let mut b_chars = b.chars();
+            let (a_char, b_char) = (a_chars.next(), b_chars.next());
+            if a_char.is_none() && b_char.is_none() {
+                return std::cmp::Ordering::Equal;
+            }
+            a = a_chars.as_str();
+            b = b_chars.as_str();
+            a_char.cmp(&b_char)
+        };
+        if ordering.is_ne() {
+            return ordering;
+        }
     }
+}
+
+fn leading_digits(s: &str) -> &str {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    &s[..end]
+}
 
+/// Read the settings from the arguments.
+/// Those not given default to the ones of the build being redone, if any.
+fn settings(c: &seahorse::Context, previous: Option<&BuildInfo>) -> anyhow::Result<Settings> {
     // Retrieve the output format from the arguments
     let out_format = c
         .string_flag("format")
-        .unwrap_or_else(|_| DEFAULT_FORMAT.to_string());
+        .ok()
+        .or_else(|| previous.map(|p| p.format.clone()))
+        .unwrap_or_else(|| DEFAULT_FORMAT.to_string());
     match out_format.as_str() {
         "jpg" => (),
         "png" => eprintln!("Beware that openseadragon has a flickering border issue with png tiles. I'd suggest using jpg instead."),
+        "avif" if cfg!(feature = "avif") => (),
+        "avif" => anyhow::bail!("avif support requires building zoomtiler with the \"avif\" feature"),
         _ => anyhow::bail!(
-            "{} is unsupported, only jpg and png are supported",
+            "{} is unsupported, only jpg, png and avif are supported",
             &out_format
         ),
     }
 
-    // Create output directory.
+    let mut encoding = TileEncoding::new(&out_format);
+    let quality = c.int_flag("quality").ok();
+    if let Some(quality) = quality.or_else(|| previous.map(|p| p.quality as isize)) {
+        if !(1..=100).contains(&quality) {
+            anyhow::bail!("Quality must be between 1 and 100");
+        }
+        encoding.jpeg_quality = quality as u8;
+    }
+    let mut flags = Flags::new(c, previous);
+    if let Some(subsampling) = flags.string("jpeg-subsampling")? {
+        encoding.jpeg_subsampling = subsampling.parse().context("Invalid --jpeg-subsampling")?;
+    }
+    if let Some(max_bytes) = flags.int("max-tile-bytes")? {
+        if max_bytes <= 0 {
+            anyhow::bail!("The maximum tile size must be > 0");
+        }
+        if out_format != "jpg" {
+            anyhow::bail!("--max-tile-bytes is only supported with jpg tiles");
+        }
+        encoding.max_tile_bytes = Some(max_bytes as usize);
+    }
+    encoding.jpeg_progressive = flags.bool("progressive")?;
+    encoding.srgb_tag = !flags.bool("no-srgb-tag")?;
+
+    let max_open_files = match c.int_flag("max-open-files") {
+        Ok(max) if max <= 0 => anyhow::bail!("The maximum number of open files must be > 0"),
+        Ok(max) => max as usize,
+        Err(_) => zoomtiler::default_max_open_files(),
+    };
+
+    let tile_size = c
+        .int_flag("tilesize")
+        .ok()
+        .or_else(|| previous.map(|p| p.tile_size as isize))
+        .unwrap_or(DEFAULT_TILE_SIZE);
+    if tile_size <= 0 {
+        anyhow::bail!("Tile size must be > 0");
+    }
+    let tile_size: usize = tile_size as usize;
+    if !c.bool_flag("quiet") {
+        eprintln!("tile_size: {}", tile_size);
+    }
+    let background = parse_color(
+        &flags
+            .string("background")?
+            .unwrap_or_else(|| DEFAULT_BACKGROUND.to_string()),
+    )
+    .context("Invalid --background")?;
+    let gap = flags.int("gap")?.unwrap_or(0);
+    if gap < 0 {
+        anyhow::bail!("Gap must be >= 0");
+    }
+    let gap_color = parse_color(
+        &flags
+            .string("gap-color")?
+            .unwrap_or_else(|| DEFAULT_GAP_COLOR.to_string()),
+    )
+    .context("Invalid --gap-color")?;
+
+    let feather = flags.int("feather")?.unwrap_or(0);
+    if feather < 0 {
+        anyhow::bail!("Feather must be >= 0");
+    }
+
+    let variants: Vec<Variant> = match flags.string("variants")? {
+        Some(list) => parse_list(&list).context("Invalid --variants")?,
+        None => Vec::new(),
+    };
+    if !variants.is_empty() && out_format != "jpg" {
+        anyhow::bail!("--variants is only supported with jpg tiles");
+    }
+    for (i, variant) in variants.iter().enumerate() {
+        if variants[..i].iter().any(|v| v.name == variant.name) {
+            anyhow::bail!("Variant {} is given twice", variant.name);
+        }
+    }
+
+    let tile_names = match flags.string("tile-name-template")? {
+        Some(template) => template.parse().context("Invalid --tile-name-template")?,
+        None => TileNameTemplate::default(),
+    };
+
+    let files_suffix = flags
+        .string("files-suffix")?
+        .unwrap_or_else(|| DEFAULT_FILES_SUFFIX.to_string());
+    if Path::new(&files_suffix).is_absolute() && !variants.is_empty() {
+        anyhow::bail!("An absolute --files-suffix cannot be shared by --variants");
+    }
+
+    let trim_tolerance = flags.int("trim-tolerance")?.unwrap_or(0);
+    if !(0..=255).contains(&trim_tolerance) {
+        anyhow::bail!("Trim tolerance must be between 0 and 255");
+    }
+
+    let trim_edges = match c.string_flag("trim-edges") {
+        Ok(list) => parse_trim_edges(&list)?,
+        Err(_) => Vec::new(),
+    };
+
+    let normalize_dpi = match flags.float("normalize-dpi")? {
+        Some(dpi) if dpi <= 0.0 => anyhow::bail!("The target dpi must be > 0"),
+        dpi => dpi,
+    };
+
+    let common_width = match flags.int("common-width")? {
+        Some(width) if width <= 0 => anyhow::bail!("Common width must be > 0"),
+        width => width.map(|width| width as usize),
+    };
+
+    let only_rows = match c.string_flag("only-rows") {
+        Ok(rows) => Some(parse_range(&rows).context("Invalid --only-rows")?),
+        Err(_) => None,
+    };
+
+    let base_only = flags.bool("base-only")?;
+    let discard_levels_above = match flags.int("discard-levels")? {
+        Some(level) if level < 0 => anyhow::bail!("The level to keep must be >= 0"),
+        Some(_) if base_only => {
+            anyhow::bail!("--discard-levels cannot be used with --base-only")
+        }
+        level => level.map(|level| level as usize),
+    };
+
+    let overview = match flags.int("overview")? {
+        Some(size) if size <= 0 => anyhow::bail!("The overview size must be > 0"),
+        Some(_) if base_only => {
+            anyhow::bail!("--overview cannot be used with --base-only, it needs the coarse levels")
+        }
+        size => size.map(|size| size as usize),
+    };
+
+    let mmap = c.bool_flag("mmap");
+    if mmap && !cfg!(feature = "mmap") {
+        anyhow::bail!("--mmap requires building zoomtiler with the \"mmap\" feature");
+    }
+    let low_memory = c.bool_flag("low-memory");
+    if low_memory && feather > 0 {
+        anyhow::bail!("--low-memory cannot be used with --feather, which needs whole images");
+    }
+
+    let level_strategy = match flags.string("level-strategy")? {
+        Some(strategy) => strategy.parse().context("Invalid --level-strategy")?,
+        None => LevelStrategy::default(),
+    };
+
+    let downsample = match flags.string("downsample-mode")? {
+        Some(mode) => mode.parse().context("Invalid --downsample-mode")?,
+        None => DownsampleMode::default(),
+    };
+
+    let tile_order = match c.string_flag("tile-order") {
+        Ok(order) => order.parse().context("Invalid --tile-order")?,
+        Err(_) => TileOrder::default(),
+    };
+    // Streamed images are decoded again from the top for every tile above the previous one.
+    if low_memory && tile_order != TileOrder::Column {
+        anyhow::bail!("--low-memory can only be used with the column --tile-order");
+    }
+
+    let io_retries = c.int_flag("io-retries").unwrap_or(DEFAULT_IO_RETRIES);
+    if io_retries < 0 {
+        anyhow::bail!("IO retries must be >= 0");
+    }
+    let io_retries = io_retries as usize;
+
+    let skip_color = match flags.string("skip-color")? {
+        Some(hex) => {
+            let color = parse_color(&hex).context("Invalid --skip-color")?;
+            let tolerance = flags.int("skip-tolerance")?.unwrap_or(0);
+            if !(0..=255).contains(&tolerance) {
+                anyhow::bail!("Skip tolerance must be between 0 and 255");
+            }
+            if only_rows.is_some() {
+                anyhow::bail!("--skip-color cannot be used with --only-rows");
+            }
+            Some((color, tolerance as u8))
+        }
+        None => None,
+    };
+
+    let stitch_only = match c.string_flag("stitch-only") {
+        Ok(path) => {
+            let path = PathBuf::from(path);
+            stitch_format(&path)?;
+            Some(path)
+        }
+        Err(_) => None,
+    };
+
+    let debug_grid = if flags.bool("debug-grid-all-levels")? {
+        DebugGrid::AllLevels
+    } else if flags.bool("debug-grid")? {
+        DebugGrid::BaseLevel
+    } else {
+        DebugGrid::Off
+    };
+    // The lines would be drawn on the tiles of the skip color, and write them anyway.
+    if debug_grid != DebugGrid::Off && skip_color.is_some() {
+        anyhow::bail!("--debug-grid cannot be used with --skip-color");
+    }
+
+    let input_order = match flags.string("sort")? {
+        Some(order) => order.parse().context("Invalid --sort")?,
+        None => InputOrder::default(),
+    };
+    let grayscale = flags.bool("grayscale")?;
+    let autotrim = flags.bool("autotrim")?;
+
+    let height_tolerance = match c.string_flag("height-tolerance") {
+        Ok(tolerance) => tolerance.parse().context("Invalid --height-tolerance")?,
+        Err(_) => HeightTolerance::Pixels(0),
+    };
+
+    Ok(Settings {
+        encoding,
+        grayscale,
+        strict_height_tolerance: Some(height_tolerance).filter(|_| c.bool_flag("strict")),
+        reference_height_from: c.string_flag("reference-height-from").ok(),
+        allow_network: c.bool_flag("allow-network"),
+        force: c.bool_flag("force"),
+        strict: c.bool_flag("strict"),
+        quiet: c.bool_flag("quiet"),
+        verbose: c.bool_flag("verbose"),
+        input_order,
+        stdout_dzi: c.bool_flag("stdout-dzi"),
+        stitch_only,
+        only_rows,
+        normalize_dpi,
+        common_width,
+        max_open_files,
+        io_retries,
+        variants,
+        tile_names,
+        files_suffix,
+        checksums: c.bool_flag("checksums"),
+        overview,
+        skip_color,
+        trim_tolerance: Some(trim_tolerance as u8).filter(|_| autotrim),
+        trim_edges,
+        flags: flags.recorded,
+        config: TilingConfig {
+            tile_size,
+            background,
+            gap: gap as usize,
+            gap_color,
+            feather: feather as usize,
+            level_strategy,
+            downsample,
+            base_only,
+            mmap,
+            low_memory,
+            discard_levels_above,
+            debug_grid,
+            tile_order,
+            print_tile_sources: !c.bool_flag("quiet"),
+        },
+    })
+}
+
+/// Bound the number of threads used to generate tiles.
+/// The global thread pool can only be configured once, for all the panoramas of a run.
+fn configure_threads(c: &seahorse::Context) -> anyhow::Result<()> {
+    if let Ok(threads) = c.int_flag("threads") {
+        if threads <= 0 {
+            anyhow::bail!("The number of threads must be > 0");
+        }
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads as usize)
+            .build_global()
+            .context("Failed to configure the thread pool")?;
+    }
+    Ok(())
+}
+
+fn run(c: &seahorse::Context) -> anyhow::Result<()> {
+    configure_threads(c)?;
     let dzi_output_path = PathBuf::from(
         c.string_flag("output")
             .unwrap_or_else(|_| DEFAULT_OUTPUT.to_string()),
@@ -78,298 +885,1340 @@ fn run(c: &seahorse::Context) -> anyhow::Result<()> {
             dzi_output_path.display()
         );
     }
+
+    if let Ok(root) = c.string_flag("batch") {
+        return run_batch(c, Path::new(&root), &dzi_output_path);
+    }
+
+    // Rebuilds without inputs default to the settings and inputs of the previous build.
+    // Builds of new inputs start again from the default settings.
+    let previous = BuildInfo::load(&build_info_path(&dzi_output_path))?;
+    let dzi_dir = dzi_output_path.parent().unwrap_or_else(|| Path::new(""));
+    let rebuild = previous.as_ref().filter(|_| c.args.is_empty());
+    let previous_inputs: Vec<PathBuf> = rebuild
+        .iter()
+        .flat_map(|p| &p.inputs)
+        .map(|input| recorded_input_path(input, dzi_dir))
+        .collect();
+    let img_paths: Vec<&Path> = if c.args.is_empty() {
+        previous_inputs.iter().map(PathBuf::as_path).collect()
+    } else {
+        c.args.iter().map(Path::new).collect()
+    };
+    if img_paths.is_empty() {
+        anyhow::bail!("At least one input image is needed");
+    }
+    let mut settings = settings(c, rebuild)?;
+    let input_flag = |name: &str| {
+        let recorded = || rebuild.and_then(|p| p.settings.get(name).cloned());
+        c.string_flag(name).ok().or_else(recorded)
+    };
+    if let Some(list) = input_flag("trim-edges") {
+        settings.trim_edges = parse_trim_edges(&list)?;
+    }
+    settings.reference_height_from = input_flag("reference-height-from");
+
+    // Retrieve the vertical offsets of each image.
+    let offsets: Vec<isize> = match input_flag("offsets") {
+        Some(list) => parse_list(&list).context("Invalid --offsets")?,
+        None => vec![0; img_paths.len()],
+    };
+    if offsets.len() != img_paths.len() {
+        anyhow::bail!(
+            "There are {} offsets for {} images",
+            offsets.len(),
+            img_paths.len()
+        );
+    }
+
+    // Retrieve the clockwise rotation of each image.
+    let rotations: Vec<Rotation> = match input_flag("rotate") {
+        Some(list) => parse_list(&list).context("Invalid --rotate")?,
+        None => vec![Rotation::None; img_paths.len()],
+    };
+    if rotations.len() != img_paths.len() {
+        anyhow::bail!(
+            "There are {} rotations for {} images",
+            rotations.len(),
+            img_paths.len()
+        );
+    }
+
+    // Retrieve the explicit crop heights of each image.
+    let heights: Option<Vec<usize>> = match input_flag("heights") {
+        Some(list) => Some(parse_list(&list).context("Invalid --heights")?),
+        None => None,
+    };
+    if let Some(heights) = &heights {
+        if heights.len() != img_paths.len() {
+            anyhow::bail!(
+                "There are {} heights for {} images",
+                heights.len(),
+                img_paths.len()
+            );
+        }
+    }
+
+    // Retrieve the regions of interest written in the .dzi file.
+    let display_rects: Vec<DisplayRect> = match input_flag("display-rect") {
+        Some(list) => list
+            .split(';')
+            .map(str::parse)
+            .collect::<anyhow::Result<_>>()
+            .context("Invalid --display-rect")?,
+        None => Vec::new(),
+    };
+
+    // Reorder the inputs, with the settings given for each of them in the arguments order.
+    if let Some(reference) = &settings.reference_height_from {
+        let path = reference_image(reference, &img_paths)?;
+        settings.reference_height_from = Some(path.to_string_lossy().into_owned());
+    }
+    // Inputs of the previous build are already in order.
+    let input_order = match rebuild {
+        Some(_) if c.string_flag("sort").is_err() => InputOrder::None,
+        _ => settings.input_order,
+    };
+    let order = input_order.sort(&img_paths);
+    if settings.trim_edges.len() == img_paths.len() {
+        settings.trim_edges = permute(&settings.trim_edges, &order);
+    }
+    let heights = heights.map(|heights| permute(&heights, &order));
+
+    tile_panorama(
+        &permute(&img_paths, &order),
+        &permute(&offsets, &order),
+        &permute(&rotations, &order),
+        heights.as_deref(),
+        &display_rects,
+        &dzi_output_path,
+        &settings,
+    )?;
+    Ok(())
+}
+
+/// Tile each subdirectory of the batch root as a panorama of its sorted images,
+/// into the same subdirectory of the output directory.
+/// Failures are reported and the other panoramas still tiled, unless failing fast.
+fn run_batch(c: &seahorse::Context, root: &Path, dzi_output_path: &Path) -> anyhow::Result<()> {
+    if !c.args.is_empty() {
+        anyhow::bail!("Input images cannot be given with --batch");
+    }
+    for flag in [
+        "offsets",
+        "rotate",
+        "heights",
+        "display-rect",
+        "stitch-only",
+    ] {
+        if c.string_flag(flag).is_ok() {
+            anyhow::bail!("--{} cannot be used with --batch", flag);
+        }
+    }
+    if let Ok(suffix) = c.string_flag("files-suffix") {
+        if Path::new(&suffix).is_absolute() {
+            anyhow::bail!("An absolute --files-suffix cannot be shared by a batch");
+        }
+    }
+    let dzi_name = dzi_output_path.file_name().unwrap();
+    let output_dir = dzi_output_path
+        .parent()
+        .context("Output dzi has no parent directory")?;
+
+    let mut panorama_dirs = Vec::new();
+    let entries = std::fs::read_dir(root)
+        .with_context(|| format!("Failed to read the batch directory {}", root.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            panorama_dirs.push(path);
+        }
+    }
+    panorama_dirs.sort();
+    if panorama_dirs.is_empty() {
+        anyhow::bail!("The batch directory {} has no subdirectory", root.display());
+    }
+
+    let mut failures = 0;
+    for dir in &panorama_dirs {
+        let name = dir.file_name().unwrap();
+        let dzi_path = output_dir.join(name).join(dzi_name);
+        let result = tile_directory(c, dir, &dzi_path);
+        match &result {
+            Ok((width, height)) => eprintln!(
+                "Panorama {}: {}x{} pixels in {}",
+                dir.display(),
+                width,
+                height,
+                dzi_path.display()
+            ),
+            Err(err) => {
+                eprintln!("Panorama {} failed: {:#}", dir.display(), err);
+                failures += 1;
+            }
+        }
+        if c.bool_flag("fail-fast") {
+            result.with_context(|| format!("Panorama {} failed", dir.display()))?;
+        }
+    }
+    if failures > 0 {
+        anyhow::bail!("{} of {} panoramas failed", failures, panorama_dirs.len());
+    }
+    Ok(())
+}
+
+/// Tile the sorted images of a directory as one panorama.
+fn tile_directory(
+    c: &seahorse::Context,
+    dir: &Path,
+    dzi_path: &Path,
+) -> anyhow::Result<(usize, usize)> {
+    let mut img_paths = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && image::ImageFormat::from_path(&path).is_ok() {
+            img_paths.push(path);
+        }
+    }
+    img_paths.sort();
+    if img_paths.is_empty() {
+        anyhow::bail!("No input image in {}", dir.display());
+    }
+    let settings = settings(c, None)?;
+    let img_paths: Vec<&Path> = img_paths.iter().map(PathBuf::as_path).collect();
+    let img_paths = permute(&img_paths, &settings.input_order.sort(&img_paths));
+    let offsets = vec![0; img_paths.len()];
+    let rotations = vec![Rotation::None; img_paths.len()];
+    tile_panorama(
+        &img_paths,
+        &offsets,
+        &rotations,
+        None,
+        &[],
+        dzi_path,
+        &settings,
+    )
+}
+
+/// Generate the tiles and the .dzi file of a panorama, and return its size.
+fn tile_panorama(
+    img_paths: &[&Path],
+    offsets: &[isize],
+    rotations: &[Rotation],
+    heights: Option<&[usize]>,
+    display_rects: &[DisplayRect],
+    dzi_output_path: &Path,
+    settings: &Settings,
+) -> anyhow::Result<(usize, usize)> {
+    let start = std::time::Instant::now();
+    if settings.verbose {
+        eprintln!("Inputs, from left to right:");
+        for (id, path) in img_paths.iter().enumerate() {
+            eprintln!("  {}: {}", id, path.display());
+        }
+    }
+    let reference_path = match &settings.reference_height_from {
+        Some(reference) => Some(reference_image(reference, img_paths)?),
+        None => None,
+    };
+    if reference_path.is_some() && heights.is_some() {
+        anyhow::bail!("--reference-height-from cannot be used with --heights");
+    }
+
+    let trim_edges = match settings.trim_edges[..] {
+        [] => vec![(0, 0); img_paths.len()],
+        [edges] => vec![edges; img_paths.len()],
+        ref edges if edges.len() == img_paths.len() => edges.to_vec(),
+        ref edges => anyhow::bail!(
+            "There are {} edge trims for {} images",
+            edges.len(),
+            img_paths.len()
+        ),
+    };
+
+    // Each variant of the tiles has its own .dzi file and tiles directory.
     let output_name = dzi_output_path.file_stem().unwrap().to_str().unwrap();
     let output_dir = dzi_output_path
         .parent()
         .context("Output dzi has no parent directory")?;
-    let img_output_dir = output_dir.join(format!("{}_files", output_name));
-    std::fs::create_dir_all(&img_output_dir)?;
+    let outputs: Vec<(PathBuf, TileEncoding)> = if settings.variants.is_empty() {
+        vec![(dzi_output_path.to_path_buf(), settings.encoding.clone())]
+    } else {
+        let variant_output = |variant: &Variant| {
+            let mut encoding = settings.encoding.clone();
+            encoding.jpeg_quality = variant.quality;
+            let dzi_name = format!("{}_{}.dzi", output_name, variant.name);
+            (output_dir.join(dzi_name), encoding)
+        };
+        settings.variants.iter().map(variant_output).collect()
+    };
+
+    // Create output directories.
+    let img_output_dirs: Vec<PathBuf> = outputs
+        .iter()
+        .map(|(dzi, _)| files_dir(dzi, &settings.files_suffix))
+        .collect();
+    let tiles_exist = img_output_dirs.iter().all(|dir| dir.is_dir());
+    if !settings.stdout_dzi && settings.stitch_only.is_none() {
+        // The tiles may be elsewhere with an absolute --files-suffix.
+        std::fs::create_dir_all(output_dir)?;
+        for dir in &img_output_dirs {
+            std::fs::create_dir_all(dir)?;
+        }
+    }
+
+    // Download the images given as URLs.
+    // They are kept in memory so that they are only downloaded once.
+    let mut img_bytes = Vec::with_capacity(img_paths.len());
+    for path in img_paths {
+        img_bytes.push(if zoomtiler::is_url(path) {
+            Some(fetch(path, settings.allow_network)?)
+        } else {
+            None
+        });
+    }
 
     // Read the image sizes.
     let mut img_sizes = Vec::with_capacity(img_paths.len());
-    for path in &img_paths {
-        let size = imagesize::size(path)?;
-        img_sizes.push((size.width, size.height));
-        eprintln!("height: {}", size.height);
+    for ((path, bytes), rotation) in img_paths.iter().zip(&img_bytes).zip(rotations) {
+        let size = match bytes {
+            Some(bytes) => imagesize::blob_size(bytes),
+            None => imagesize::size(path),
+        }
+        .with_context(|| format!("Failed to read the size of {}", path.display()))?;
+        img_sizes.push(rotation.size(size.width, size.height));
+        if !settings.quiet {
+            eprintln!("height: {}", size.height);
+        }
     }
 
-    // Assert that all image heights are the same.
-    let height = img_sizes.iter().min_by_key(|(_, h)| h).unwrap().1;
-    for ((_, h), path) in img_sizes.iter().zip(&img_paths) {
-        if *h > height {
+    // Skip the images with invalid sizes, like corrupt files, or fail if strict.
+    let mut valid = vec![true; img_paths.len()];
+    for ((path, &(width, height)), valid) in img_paths.iter().zip(&img_sizes).zip(&mut valid) {
+        let max = zoomtiler::MAX_DIMENSION;
+        if width == 0 || height == 0 || width > max || height > max {
+            if settings.strict {
+                anyhow::bail!(
+                    "Image {} has an invalid size {}x{}",
+                    path.display(),
+                    width,
+                    height
+                );
+            }
             eprintln!(
-                "BEWARE that image {} has height {} > {}",
+                "BEWARE that image {} has an invalid size {}x{}, it is skipped",
                 path.display(),
-                h,
+                width,
                 height
             );
+            *valid = false;
+        }
+    }
+    if !valid.iter().any(|&v| v) {
+        anyhow::bail!("No input image has a valid size");
+    }
+    let valid_paths = keep_valid(img_paths.iter().copied(), &valid);
+    let img_paths = &valid_paths[..];
+    let valid_offsets = keep_valid(offsets.iter().copied(), &valid);
+    let offsets = &valid_offsets[..];
+    let rotations = keep_valid(rotations.iter().copied(), &valid);
+    let trim_edges = keep_valid(trim_edges, &valid);
+    let heights = heights.map(|heights| keep_valid(heights.iter().copied(), &valid));
+    let heights = heights.as_deref();
+    let img_bytes = keep_valid(img_bytes, &valid);
+    let mut img_sizes = keep_valid(img_sizes, &valid);
+
+    // Resize the images whose resolution differs from the target one,
+    // so that all pixels of the panorama have the same physical size.
+    let mut img_resizes = vec![None; img_paths.len()];
+    if let Some(target_dpi) = settings.normalize_dpi {
+        let sizes_and_resizes = img_sizes.iter_mut().zip(&mut img_resizes);
+        for ((path, bytes), ((width, height), resize)) in
+            img_paths.iter().zip(&img_bytes).zip(sizes_and_resizes)
+        {
+            let header = metadata_header(path, bytes.as_deref())?;
+            match zoomtiler::read_dpi(&header) {
+                Some(dpi) if (dpi - target_dpi).abs() > 0.01 => {
+                    let scale = target_dpi / dpi;
+                    *width = ((*width as f64 * scale).round() as usize).max(1);
+                    *height = ((*height as f64 * scale).round() as usize).max(1);
+                    *resize = Some((*width, *height));
+                    eprintln!(
+                        "Resizing {} from {:.0} to {} dpi, to {}x{}",
+                        path.display(),
+                        dpi,
+                        target_dpi,
+                        width,
+                        height
+                    );
+                }
+                Some(_) => (),
+                None => eprintln!(
+                    "BEWARE that image {} has no dpi metadata, it is not resized",
+                    path.display()
+                ),
+            }
+        }
+    }
+
+    // Detect the uniform borders to trim, which requires decoding the images.
+    let mut img_trims = vec![Borders::default(); img_paths.len()];
+    if let Some(tolerance) = settings.trim_tolerance {
+        let sizes_and_trims = img_sizes.iter_mut().zip(&mut img_trims);
+        for ((((path, bytes), rotation), resize), ((width, height), trim)) in img_paths
+            .iter()
+            .zip(&img_bytes)
+            .zip(&rotations)
+            .zip(&img_resizes)
+            .zip(sizes_and_trims)
+        {
+            let img = match bytes {
+                Some(bytes) => image::load_from_memory(bytes),
+                None => image::open(path),
+            }
+            .with_context(|| format!("Failed to decode {}", path.display()))?;
+            let mut img = rotation.apply(img);
+            if let Some((width, height)) = *resize {
+                img = img.resize_exact(width as u32, height as u32, Lanczos3);
+            }
+            *trim = detect_borders(&img.into_rgb8(), tolerance);
+            *width -= trim.left + trim.right;
+            *height -= trim.top + trim.bottom;
+            eprintln!("Trimming {:?} from {}", trim, path.display());
+        }
+    }
+
+    // Crop the edge columns given explicitly, like seams duplicated in neighbor images.
+    let sizes_and_trims = img_sizes.iter_mut().zip(&mut img_trims);
+    for (((width, _), trim), (&(left, right), path)) in
+        sizes_and_trims.zip(trim_edges.iter().zip(img_paths))
+    {
+        if left + right >= *width {
+            anyhow::bail!(
+                "Image {} has width {}, trimming {},{} edge columns would leave nothing",
+                path.display(),
+                width,
+                left,
+                right
+            );
+        }
+        trim.left += left;
+        trim.right += right;
+        *width -= left + right;
+    }
+
+    match heights {
+        // Explicit heights replace the crop to the smallest image.
+        Some(heights) => {
+            for (((_, h), &height), path) in img_sizes.iter_mut().zip(heights).zip(img_paths) {
+                if height == 0 || height > *h {
+                    anyhow::bail!(
+                        "Image {} has height {}, it cannot be cropped to {}",
+                        path.display(),
+                        h,
+                        height
+                    );
+                }
+                *h = height;
+            }
+        }
+        None => {
+            // Assert that all image heights are the same as the smallest one, or the reference one.
+            let height = match reference_path {
+                Some(reference) => match img_paths.iter().position(|&path| path == reference) {
+                    Some(id) => img_sizes[id].1,
+                    None => {
+                        anyhow::bail!("The reference image {} was skipped", reference.display())
+                    }
+                },
+                None => img_sizes.iter().min_by_key(|(_, h)| h).unwrap().1,
+            };
+            for ((_, h), path) in img_sizes.iter().zip(img_paths) {
+                match settings.strict_height_tolerance {
+                    Some(tolerance) if !tolerance.allows(*h, height) => anyhow::bail!(
+                        "Image {} has height {} but the panorama height is {}",
+                        path.display(),
+                        h,
+                        height
+                    ),
+                    _ => (),
+                }
+                if *h > height {
+                    eprintln!(
+                        "BEWARE that image {} has height {} > {}",
+                        path.display(),
+                        h,
+                        height
+                    );
+                } else if *h < height {
+                    eprintln!("Padding {} from height {} to {}", path.display(), h, height);
+                }
+            }
+
+            // Crop the image sizes as will do the algorithm, shorter ones are padded.
+            img_sizes.iter_mut().for_each(|(_, h)| *h = height.min(*h));
         }
     }
 
-    // Crop the image sizes as will do the algorithm.
-    img_sizes.iter_mut().for_each(|(_, h)| *h = height);
+    // Center each image in the common width,
+    // cropping the wider ones and padding the narrower ones.
+    let mut img_pads = vec![0; img_paths.len()];
+    if let Some(common_width) = settings.common_width {
+        let sizes_and_trims = img_sizes.iter_mut().zip(&mut img_trims);
+        for (((width, _), trim), (pad, path)) in
+            sizes_and_trims.zip(img_pads.iter_mut().zip(img_paths))
+        {
+            if *width > common_width {
+                let extra = *width - common_width;
+                trim.left += extra / 2;
+                trim.right += extra - extra / 2;
+                eprintln!(
+                    "Cropping {} from width {} to {}",
+                    path.display(),
+                    width,
+                    common_width
+                );
+            } else if *width < common_width {
+                *pad = (common_width - *width) / 2;
+                eprintln!(
+                    "Padding {} from width {} to {}",
+                    path.display(),
+                    width,
+                    common_width
+                );
+            }
+            *width = common_width;
+        }
+    }
+
+    let inputs: Vec<InputImage> = img_paths
+        .iter()
+        .zip(&img_sizes)
+        .zip(offsets.iter().zip(rotations.iter().copied()))
+        .zip(img_bytes)
+        .zip(img_trims.into_iter().zip(img_pads).zip(img_resizes))
+        .map(
+            |(
+                (((path, (width, height)), (offset_y, rotation)), bytes),
+                ((trim, pad_left), resize),
+            )| {
+                InputImage {
+                    path: path.to_path_buf(),
+                    width: *width,
+                    height: *height,
+                    offset_y: *offset_y,
+                    rotation,
+                    pad_left,
+                    bytes,
+                    resize,
+                    trim,
+                }
+            },
+        )
+        .collect();
+
+    if settings.config.low_memory {
+        for input in &inputs {
+            if let Some(reason) = input.unstreamable_reason() {
+                eprintln!(
+                    "BEWARE that {} is decoded whole despite --low-memory, since {}",
+                    input.path.display(),
+                    reason
+                );
+            }
+        }
+    }
 
     // Compute the total width of the panorama.
-    let width_sum: usize = img_sizes.iter().map(|(w, _)| w).sum();
+    let (width_sum, height) = panorama_size(&inputs, &settings.config);
+    check_panorama_size(width_sum, height, &settings.config)?;
+    for rect in display_rects {
+        if rect.x + rect.width > width_sum || rect.y + rect.height > height {
+            anyhow::bail!(
+                "The display rect {},{},{},{} is outside of the {}x{} panorama",
+                rect.x,
+                rect.y,
+                rect.width,
+                rect.height,
+                width_sum,
+                height
+            );
+        }
+    }
+    let dzi_xml = |dzi_path: &Path, encoding: &TileEncoding| {
+        let dzi = DziInfo {
+            tile_size: settings.config.tile_size,
+            overlap: 0,
+            format: encoding.format.clone(),
+            width: width_sum,
+            height,
+            url: tiles_url(dzi_path, &settings.files_suffix),
+        };
+        dzi.to_xml(display_rects, base_level(width_sum, height))
+    };
 
-    // Compute the number of levels required with the tiles sizes.
-    let tile_size = c.int_flag("tilesize").unwrap_or(DEFAULT_TILE_SIZE);
-    if tile_size <= 0 {
-        anyhow::bail!("Tile size must be > 0");
+    // Only print the .dzi file, without writing anything.
+    if settings.stdout_dzi {
+        let (dzi_path, encoding) = &outputs[0];
+        println!("{}", dzi_xml(dzi_path, encoding));
+        return Ok((width_sum, height));
     }
-    let tile_size: usize = tile_size as usize;
-    eprintln!("tile_size: {}", tile_size);
-    let tile_count_width = (width_sum + tile_size - 1) / tile_size;
-    let tile_count_height = (height + tile_size - 1) / tile_size;
-    // let width_levels = levels_for(tile_count_width);
-    // let height_levels = levels_for(tile_count_height);
-
-    // let's choose a number of levels half way from the min and max
-    // which correspond to the number of levels for the smallest and longest dimensions.
-    // let levels = (width_levels + height_levels + 1) / 2;
-    let levels = levels_for(width_sum.max(height));
-    eprintln!("levels: {}", levels);
-
-    // Start generating the images at the highest resolution level.
-    // TODO: when vertical panoramas inputs will be allowed,
-    // be careful with the image access order.
-    let level_out_dir = img_output_dir.join((levels - 1).to_string());
-    std::fs::create_dir_all(&level_out_dir)?;
-    let mut extractor = ImgExtractor::new(&img_paths, &img_sizes);
-    for tx in 0..tile_count_width {
-        for ty in 0..tile_count_height {
-            let img = extractor.extract(height, tile_size, tx, ty)?;
-            img.save(img_out_path(&level_out_dir, &out_format, tx, ty))?;
-        }
-    }
-
-    // Now, we need to take 2x2 blocs of images
-    // and complete the pyramid of levels by halfing the resolution each time.
-    let mut parent_x_tiles = tile_count_width;
-    let mut parent_y_tiles = tile_count_height;
-    for parent_level in (1..levels).rev() {
-        let (child_x_tiles, child_y_tiles) = compute_half_resolutions(
-            &img_output_dir,
-            &out_format,
-            parent_level,
-            parent_x_tiles,
-            parent_y_tiles,
-        )?;
-        parent_x_tiles = child_x_tiles;
-        parent_y_tiles = child_y_tiles;
+
+    // Only save the full panorama, without any tile, .dzi file or build cache.
+    if let Some(path) = &settings.stitch_only {
+        stitch(&inputs, settings, path)?;
+        if !settings.quiet {
+            eprintln!(
+                "{}: {}x{} pixels in {:.1}s",
+                path.display(),
+                width_sum,
+                height,
+                start.elapsed().as_secs_f64()
+            );
+        }
+        return Ok((width_sum, height));
     }
 
-    // Write the ImageProperties.xml file.
-    let xml_content = format!(
-        r#"<?xml version="1.0" encoding="UTF-8"?><Image xmlns="http://schemas.microsoft.com/deepzoom/2008" TileSize="{}" Overlap="0" Format="{}"><Size Width="{}" Height="{}"/></Image>"#,
-        tile_size, out_format, width_sum, height
-    );
-    std::fs::write(&dzi_output_path, xml_content).context("Failed to write xml file")
-}
+    if !settings.quiet {
+        eprintln!(
+            "levels: {}",
+            level_count(width_sum, height, &settings.config)
+        );
+    }
 
-/// Compute half resolution images and output the number of tiles generated.
-fn compute_half_resolutions(
-    img_output_dir: &Path,
-    out_format: &str,
-    previous_lvl: usize,
-    tile_count_width: usize,
-    tile_count_height: usize,
-) -> anyhow::Result<(usize, usize)> {
-    let level_out_dir = img_output_dir.join((previous_lvl - 1).to_string());
-    std::fs::create_dir_all(&level_out_dir)?;
-    let half_tile_count_width = (tile_count_width + 1) / 2;
-    let half_tile_count_height = (tile_count_height + 1) / 2;
-    for tx in 0..half_tile_count_width {
-        for ty in 0..half_tile_count_height {
-            let img_path = |tx, ty| {
-                img_out_path(
-                    &img_output_dir.join(previous_lvl.to_string()),
-                    out_format,
-                    tx,
-                    ty,
-                )
-            };
-            let top_left: RgbImage = ImageReader::open(img_path(tx * 2, ty * 2))?
-                .decode()?
-                .into_rgb8();
-            let top_right: RgbImage = match ImageReader::open(img_path(tx * 2 + 1, ty * 2)) {
-                Ok(reader) => reader.decode()?.into_rgb8(),
-                Err(_) => RgbImage::new(0, top_left.height()),
-            };
-            let bottom_left: RgbImage = match ImageReader::open(img_path(tx * 2, ty * 2 + 1)) {
-                Ok(reader) => reader.decode()?.into_rgb8(),
-                Err(_) => RgbImage::new(top_left.width(), 0),
+    // Only regenerate the tiles of the inputs that changed since the previous build.
+    // Rows of tiles given explicitly are regenerated instead, whatever changed,
+    // and the cache of the previous build is kept as is for the next one.
+    let cache_path = output_dir.join(format!("{}.cache", output_name));
+    let cache = build_cache(&inputs, settings, (width_sum, height))?;
+    let all_columns = 0..width_sum;
+    let (changed_columns, changed_rows) = match &settings.only_rows {
+        Some(rows) => {
+            let tile_size = settings.config.tile_size;
+            let tile_rows = height.div_ceil(tile_size);
+            if rows.start >= rows.end || rows.end > tile_rows {
+                anyhow::bail!(
+                    "--only-rows {}..{} is not within the {} rows of tiles",
+                    rows.start,
+                    rows.end,
+                    tile_rows
+                );
+            }
+            let changed_rows = rows.start * tile_size..rows.end * tile_size;
+            (vec![all_columns], changed_rows)
+        }
+        None => {
+            let previous_cache = match BuildCache::load(&cache_path)? {
+                Some(previous) if tiles_exist && !settings.force => Some(previous),
+                _ => None,
             };
-            let bottom_right: RgbImage = match ImageReader::open(img_path(tx * 2 + 1, ty * 2 + 1)) {
-                Ok(reader) => reader.decode()?.into_rgb8(),
-                Err(_) => RgbImage::new(top_right.width(), bottom_left.height()),
+            let changed_columns = match previous_cache.and_then(|p| cache.changed_columns(&p)) {
+                Some(columns) => {
+                    eprintln!("Regenerating {} changed area(s)", columns.len());
+                    // Tiles blended across a seam also change with the image on the other side.
+                    let config = &settings.config;
+                    let feather = if config.gap == 0 { config.feather } else { 0 };
+                    let blended = |range: Range<usize>| {
+                        range.start.saturating_sub(feather / 2)..range.end + feather.div_ceil(2)
+                    };
+                    columns.into_iter().map(blended).collect()
+                }
+                None => vec![all_columns],
             };
-            let half_img: RgbImage = half_res(top_left, top_right, bottom_left, bottom_right);
-            half_img.save(img_out_path(&level_out_dir, out_format, tx, ty))?;
-        }
-    }
-    Ok((half_tile_count_width, half_tile_count_height))
-}
-
-fn half_res(
-    top_left: RgbImage,
-    top_right: RgbImage,
-    bottom_left: RgbImage,
-    bottom_right: RgbImage,
-) -> RgbImage {
-    assert_eq!(top_left.width(), bottom_left.width());
-    assert_eq!(top_left.height(), top_right.height());
-    assert_eq!(bottom_right.width(), top_right.width());
-    assert_eq!(bottom_right.height(), bottom_left.height());
-    let half_width = (top_left.width() + top_right.width() + 1) / 2;
-    let half_height = (top_left.height() + bottom_left.height() + 1) / 2;
-
-    // Helper closure to use the correct image to retrieve a given pixel.
-    let img_source_and_offset = |x, y| {
-        if x < top_left.width() && y < top_left.height() {
-            (&top_left, 0, 0)
-        } else if x < top_left.width() {
-            (&bottom_left, 0, top_left.height())
-        } else if y < top_left.height() {
-            (&top_right, top_left.width(), 0)
-        } else {
-            (&bottom_right, top_left.width(), top_left.height())
+            // Remove the cache while tiles are modified, in case the generation fails.
+            if cache_path.exists() {
+                std::fs::remove_file(&cache_path).context("Failed to remove the build cache")?;
+            }
+            (changed_columns, 0..height)
         }
     };
-    // Helper closure to extract the correct pixel from the correct image.
-    let extract_pixel = |target_x, target_y| {
-        let (img, offset_x, offset_y) = img_source_and_offset(target_x, target_y);
-        let local_x = target_x - offset_x;
-        let local_y = target_y - offset_y;
-        if local_x >= img.width() || local_y >= img.height() {
-            None
+
+    // Tiles are computed once, and encoded for every variant.
+    // Grayscale tiles only store one channel instead of three.
+    // All variants share the same limit of open files.
+    let open_files = Arc::new(OpenFilesLimit::new(settings.max_open_files));
+    let sinks = img_output_dirs
+        .into_iter()
+        .zip(&outputs)
+        .map(|(dir, (_, encoding))| {
+            let sink = FsTileSink::new(dir, encoding.clone())
+                .with_tile_names(settings.tile_names.clone())
+                .with_open_files_limit(open_files.clone())
+                .with_io_retries(settings.io_retries);
+            if settings.checksums {
+                sink.with_checksums()
+            } else {
+                sink
+            }
+        })
+        .collect();
+    let sink = MultiTileSink::new(sinks);
+    let size = (width_sum, height);
+    let (sink, overview, skipped_tiles) = match settings.skip_color {
+        Some((color, tolerance)) => {
+            let skip_sink = SkipColorSink::new(sink, color, tolerance);
+            update_pixels(
+                &inputs,
+                settings,
+                &skip_sink,
+                &changed_columns,
+                changed_rows,
+            )?;
+            if !settings.quiet {
+                eprintln!("Skipped {} tiles of the skip color", skip_sink.skipped());
+            }
+            let overview = save_overview(&skip_sink, settings, size, output_dir)?;
+            let skipped_tiles = skip_sink.skipped_tiles();
+            (skip_sink.into_inner(), overview, skipped_tiles)
+        }
+        None => {
+            update_pixels(&inputs, settings, &sink, &changed_columns, changed_rows)?;
+            let overview = save_overview(&sink, settings, size, output_dir)?;
+            (sink, overview, Vec::new())
+        }
+    };
+    // Discarded and skipped tiles cannot be reused by an incremental rebuild.
+    let reusable = settings.config.discard_levels_above.is_none() && settings.skip_color.is_none();
+    if settings.only_rows.is_none() && reusable {
+        cache.save(&cache_path)?;
+    }
+    if !settings.quiet {
+        let (tiles, bytes) = sink.sinks().iter().fold((0, 0), |(tiles, bytes), sink| {
+            let (written_tiles, written_bytes) = sink.written();
+            (tiles + written_tiles, bytes + written_bytes)
+        });
+        let levels = if settings.config.base_only {
+            1
         } else {
-            Some(*img.get_pixel(local_x, local_y))
-        }
-    };
-    RgbImage::from_fn(half_width, half_height, |x, y| {
-        let pixels = [
-            extract_pixel(2 * x, 2 * y),
-            extract_pixel(2 * x + 1, 2 * y),
-            extract_pixel(2 * x, 2 * y + 1),
-            extract_pixel(2 * x + 1, 2 * y + 1),
-        ];
-        // Compute the mean of the 4 subpixels (maybe less if near a border)
-        let (pix_count, pix_sum): (u16, [u16; 3]) = IntoIterator::into_iter(pixels).fold(
-            (0, [0, 0, 0]),
-            |(count, [r_sum, g_sum, b_sum]), maybe_pix| match maybe_pix {
-                Some(pix) => (
-                    count + 1,
-                    [
-                        r_sum + pix.0[0] as u16,
-                        g_sum + pix.0[1] as u16,
-                        b_sum + pix.0[2] as u16,
-                    ],
-                ),
-                None => (count, [r_sum, g_sum, b_sum]),
-            },
+            level_count(width_sum, height, &settings.config)
+        };
+        eprintln!(
+            "{}: {} levels, {} tiles, {} in {:.1}s",
+            dzi_output_path.display(),
+            levels,
+            tiles,
+            human_bytes(bytes),
+            start.elapsed().as_secs_f64()
+        );
+    }
+    let over_budget: usize = sink.sinks().iter().map(FsTileSink::over_budget).sum();
+    if over_budget > 0 {
+        eprintln!(
+            "BEWARE that {} tiles are over the --max-tile-bytes budget",
+            over_budget
+        );
+    }
+
+    // Update the hashes of the tiles, keeping the ones of tiles not written by this build.
+    if settings.checksums {
+        let checksums_path = output_dir.join(CHECKSUMS_FILE);
+        let mut checksums = Checksums::load(&checksums_path)?;
+        checksums
+            .entries
+            .retain(|file, _| output_dir.join(file).is_file());
+        for sink in sink.sinks() {
+            for (path, hash) in sink.checksums().unwrap_or_default() {
+                let file = path.strip_prefix(output_dir).unwrap_or(&path);
+                checksums
+                    .entries
+                    .insert(file.to_string_lossy().into_owned(), hash);
+            }
+        }
+        checksums.save(&checksums_path)?;
+    }
+
+    // Write the ImageProperties.xml files.
+    for (dzi_path, encoding) in &outputs {
+        std::fs::write(dzi_path, dzi_xml(dzi_path, encoding))
+            .context("Failed to write xml file")?;
+    }
+
+    // Record the settings of the build, to rebuild matching tiles later,
+    // with those of each input in the order they are stitched.
+    let mut recorded = settings.flags.clone();
+    let mut record = |name: &str, values: Vec<String>, separator: &str| {
+        recorded.insert(name.to_string(), values.join(separator));
+    };
+    if offsets.iter().any(|&offset| offset != 0) {
+        record(
+            "offsets",
+            offsets.iter().map(isize::to_string).collect(),
+            ",",
+        );
+    }
+    if rotations.iter().any(|&rotation| rotation != Rotation::None) {
+        record(
+            "rotate",
+            rotations.iter().map(Rotation::to_string).collect(),
+            ",",
         );
-        Rgb([
-            (pix_sum[0] / pix_count) as u8,
-            (pix_sum[1] / pix_count) as u8,
-            (pix_sum[2] / pix_count) as u8,
-        ])
+    }
+    if let Some(heights) = heights {
+        record(
+            "heights",
+            heights.iter().map(usize::to_string).collect(),
+            ",",
+        );
+    }
+    if trim_edges.iter().any(|&edges| edges != (0, 0)) {
+        let edges = trim_edges.iter().map(|(l, r)| format!("{},{}", l, r));
+        record("trim-edges", edges.collect(), ";");
+    }
+    if let Some(reference) = reference_path {
+        let id = img_paths
+            .iter()
+            .position(|&path| path == reference)
+            .unwrap();
+        record("reference-height-from", vec![id.to_string()], "");
+    }
+    if !display_rects.is_empty() {
+        let rects = display_rects
+            .iter()
+            .map(|r| format!("{},{},{},{}", r.x, r.y, r.width, r.height));
+        record("display-rect", rects.collect(), ";");
+    }
+    let inputs = img_paths
+        .iter()
+        .map(|path| recorded_input(path, output_dir))
+        .collect::<anyhow::Result<_>>()?;
+    let build_info = BuildInfo {
+        tile_size: settings.config.tile_size,
+        overlap: 0,
+        format: settings.encoding.format.clone(),
+        quality: settings.encoding.jpeg_quality,
+        inputs,
+        settings: recorded,
+        levels: Some(generated_levels(width_sum, height, &settings.config)),
+        overview,
+        skipped_tiles,
+    };
+    build_info.save(&build_info_path(dzi_output_path))?;
+    Ok((width_sum, height))
+}
+
+/// Update the tiles with the pixel type of the settings.
+fn update_pixels<S: TileSink<Luma<u8>> + TileSink<Rgb<u8>>>(
+    inputs: &[InputImage],
+    settings: &Settings,
+    sink: &S,
+    changed_columns: &[Range<usize>],
+    changed_rows: Range<usize>,
+) -> anyhow::Result<()> {
+    let config = &settings.config;
+    if settings.grayscale {
+        update_tiles::<Luma<u8>, _>(inputs, config, sink, changed_columns, changed_rows)
+    } else {
+        update_tiles::<Rgb<u8>, _>(inputs, config, sink, changed_columns, changed_rows)
+    }
+}
+
+/// Largest width or height of a jpg image, whose dimensions are stored on 16 bits.
+const MAX_JPEG_DIMENSION: usize = u16::MAX as usize;
+
+/// Format of the stitched panorama, given by the extension of its path.
+fn stitch_format(path: &Path) -> anyhow::Result<&'static str> {
+    match path.extension().and_then(OsStr::to_str) {
+        Some("jpg") | Some("jpeg") => Ok("jpg"),
+        Some("png") => Ok("png"),
+        _ => anyhow::bail!(
+            "The stitched panorama {} is not a .jpg or .png file",
+            path.display()
+        ),
+    }
+}
+
+/// Save the full resolution panorama as a single image, encoded like the tiles would be.
+fn stitch(inputs: &[InputImage], settings: &Settings, path: &Path) -> anyhow::Result<()> {
+    let config = &settings.config;
+    let mut encoding = settings.encoding.clone();
+    encoding.format = stitch_format(path)?.to_string();
+    let (width, height) = panorama_size(inputs, config);
+    let too_large = width > MAX_JPEG_DIMENSION || height > MAX_JPEG_DIMENSION;
+    if encoding.format == "jpg" && too_large {
+        anyhow::bail!(
+            "The panorama of {}x{} pixels is too large for a jpg image \
+            (at most {} pixels on each side), use a .png file instead",
+            width,
+            height,
+            MAX_JPEG_DIMENSION
+        );
+    }
+    let bytes = if settings.grayscale {
+        encode_tile(&stitch_panorama::<Luma<u8>>(inputs, config)?, &encoding)?
+    } else {
+        encode_tile(&stitch_panorama::<Rgb<u8>>(inputs, config)?, &encoding)?
+    };
+    if let Some(dir) = path.parent().filter(|dir| *dir != Path::new("")) {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, bytes)
+        .with_context(|| format!("Failed to write the stitched panorama {}", path.display()))
+}
+
+/// Save the overview of the panorama in the output directory, if one is asked for,
+/// from the coarse tiles in the sink.
+fn save_overview<S: TileSink<Luma<u8>> + TileSink<Rgb<u8>>>(
+    sink: &S,
+    settings: &Settings,
+    (width, height): (usize, usize),
+    output_dir: &Path,
+) -> anyhow::Result<Option<Overview>> {
+    let max_size = match settings.overview {
+        Some(max_size) => max_size,
+        None => return Ok(None),
+    };
+    let config = &settings.config;
+    let mut encoding = settings.encoding.clone();
+    encoding.format = "jpg".to_string();
+    let (bytes, (overview_width, overview_height)) = if settings.grayscale {
+        let img = overview::<Luma<u8>, _>(sink, width, height, config, max_size)?;
+        (encode_tile(&img, &encoding)?, img.dimensions())
+    } else {
+        let img = overview::<Rgb<u8>, _>(sink, width, height, config, max_size)?;
+        (encode_tile(&img, &encoding)?, img.dimensions())
+    };
+    let path = output_dir.join(OVERVIEW_FILE);
+    std::fs::write(&path, bytes)
+        .with_context(|| format!("Failed to write the overview {}", path.display()))?;
+    Ok(Some(Overview {
+        file: OVERVIEW_FILE.to_string(),
+        width: overview_width as usize,
+        height: overview_height as usize,
+    }))
+}
+
+/// Items in the order of the given indices.
+fn permute<T: Clone>(items: &[T], indices: &[usize]) -> Vec<T> {
+    indices.iter().map(|&i| items[i].clone()).collect()
+}
+
+/// Keep the items whose validity flag is set.
+fn keep_valid<T>(items: impl IntoIterator<Item = T>, valid: &[bool]) -> Vec<T> {
+    let items = items.into_iter().zip(valid);
+    items
+        .filter(|(_, &valid)| valid)
+        .map(|(item, _)| item)
+        .collect()
+}
+
+/// Format a number of bytes with a binary unit, like 12.3 MiB.
+fn human_bytes(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < units.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, units[unit])
+    }
+}
+
+/// Input image given by its path or index in the inputs.
+fn reference_image<'a>(reference: &str, img_paths: &[&'a Path]) -> anyhow::Result<&'a Path> {
+    if let Some(path) = img_paths.iter().find(|&&path| path == Path::new(reference)) {
+        return Ok(path);
+    }
+    match reference.parse::<usize>() {
+        Ok(id) if id < img_paths.len() => Ok(img_paths[id]),
+        Ok(id) => anyhow::bail!(
+            "There is no reference image {} among {} images",
+            id,
+            img_paths.len()
+        ),
+        Err(_) => anyhow::bail!("The reference image {} is not an input", reference),
+    }
+}
+
+/// Directory of the tiles of a .dzi file, `NAME{suffix}` next to `NAME.dzi`,
+/// or the suffix itself if it is an absolute path.
+fn files_dir(dzi_path: &Path, suffix: &str) -> PathBuf {
+    if Path::new(suffix).is_absolute() {
+        return PathBuf::from(suffix);
+    }
+    let name = dzi_path.file_stem().unwrap().to_str().unwrap();
+    dzi_path.with_file_name(format!("{}{}", name, suffix))
+}
+
+/// Url of the tiles directory recorded in the .dzi file, relative to it,
+/// for viewers to find tiles not in the standard `NAME_files` directory.
+fn tiles_url(dzi_path: &Path, suffix: &str) -> Option<String> {
+    if suffix == DEFAULT_FILES_SUFFIX {
+        None
+    } else if Path::new(suffix).is_absolute() {
+        Some(format!("{}/", suffix.trim_end_matches('/')))
+    } else {
+        let name = dzi_path.file_stem().unwrap().to_str().unwrap();
+        Some(format!("{}{}/", name, suffix))
+    }
+}
+
+/// Companion file of a .dzi file with the settings of its build, `NAME.build.json`.
+fn build_info_path(dzi_path: &Path) -> PathBuf {
+    dzi_path.with_extension("build.json")
+}
+
+/// Input image as recorded in the build info, relative to the directory of the .dzi file,
+/// so that the build can be done again from elsewhere, or once moved with its inputs.
+fn recorded_input(path: &Path, dzi_dir: &Path) -> anyhow::Result<String> {
+    if zoomtiler::is_url(path) {
+        return Ok(path.to_string_lossy().into_owned());
+    }
+    let cwd = std::env::current_dir().context("Failed to read the current directory")?;
+    let path = normalize(&cwd.join(path));
+    let dzi_dir = normalize(&cwd.join(dzi_dir));
+    let common = path
+        .components()
+        .zip(dzi_dir.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    // Paths on different drives have nothing in common.
+    if common == 0 {
+        return Ok(path.to_string_lossy().into_owned());
+    }
+    let mut relative: PathBuf = dzi_dir
+        .components()
+        .skip(common)
+        .map(|_| Component::ParentDir)
+        .collect();
+    relative.extend(path.components().skip(common));
+    Ok(relative.to_string_lossy().into_owned())
+}
+
+/// Path of an input image recorded in the build info, from the current directory.
+fn recorded_input_path(input: &str, dzi_dir: &Path) -> PathBuf {
+    if zoomtiler::is_url(Path::new(input)) {
+        return PathBuf::from(input);
+    }
+    normalize(&dzi_dir.join(input))
+}
+
+/// Remove the `.` components of a path, and the `..` ones with their parent,
+/// without following symbolic links.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Describe the inputs and settings of a build, to compare with the next one.
+fn build_cache(
+    inputs: &[InputImage],
+    settings: &Settings,
+    (width, height): (usize, usize),
+) -> anyhow::Result<BuildCache> {
+    // Printing progress does not change the tiles.
+    let config = TilingConfig {
+        print_tile_sources: false,
+        ..settings.config.clone()
+    };
+    let settings_description = format!(
+        "{:?} {:?} {:?} {:?} trim={:?} grayscale={} skip={:?}",
+        config,
+        settings.encoding,
+        settings.variants,
+        settings.tile_names,
+        settings.trim_tolerance,
+        settings.grayscale,
+        settings.skip_color
+    );
+    let mut entries = Vec::with_capacity(inputs.len());
+    let mut left = 0;
+    for input in inputs {
+        let hash = match &input.bytes {
+            Some(bytes) => content_hash(bytes),
+            None => content_hash(
+                &std::fs::read(&input.path)
+                    .with_context(|| format!("Failed to read {}", input.path.display()))?,
+            ),
+        };
+        // The placement of the image in the panorama also changes its tiles.
+        let placement = format!(
+            "{:016x} offset={} rotation={:?} height={} trim={:?} pad={} resize={:?}",
+            hash,
+            input.offset_y,
+            input.rotation,
+            input.height,
+            input.trim,
+            input.pad_left,
+            input.resize
+        );
+        entries.push(CacheEntry {
+            path: input.path.display().to_string(),
+            hash: content_hash(placement.as_bytes()),
+            columns: left..left + input.width,
+        });
+        left += input.width + settings.config.gap;
+    }
+    Ok(BuildCache {
+        settings: content_hash(settings_description.as_bytes()),
+        size: (width, height),
+        levels: level_count(width, height, &settings.config),
+        entries,
     })
 }
 
-struct ImgExtractor {
-    paths: Vec<PathBuf>,
-    sizes: Vec<(usize, usize)>,
-    full_width: usize,
-    full_height: usize,
-    img_cache: HashMap<usize, RgbImage>,
+/// First bytes of an input image, where its metadata is.
+fn metadata_header(path: &Path, bytes: Option<&[u8]>) -> anyhow::Result<Vec<u8>> {
+    const HEADER_SIZE: usize = 64 * 1024;
+    if let Some(bytes) = bytes {
+        return Ok(bytes[..bytes.len().min(HEADER_SIZE)].to_vec());
+    }
+    let file =
+        std::fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    file.take(HEADER_SIZE as u64).read_to_end(&mut header)?;
+    Ok(header)
+}
+
+/// Download an input image given as an URL.
+/// Network access must be explicitly allowed to avoid surprises.
+fn fetch(url: &Path, allow_network: bool) -> anyhow::Result<Vec<u8>> {
+    if !allow_network {
+        anyhow::bail!(
+            "{} is an URL, use --allow-network to download it",
+            url.display()
+        );
+    }
+    #[cfg(feature = "http")]
+    {
+        eprintln!("Downloading {}", url.display());
+        zoomtiler::download(&url.to_string_lossy())
+            .with_context(|| format!("Failed to download {}", url.display()))
+    }
+    #[cfg(not(feature = "http"))]
+    anyhow::bail!(
+        "Downloading {} requires building zoomtiler with the \"http\" feature",
+        url.display()
+    )
 }
 
-impl ImgExtractor {
-    fn new(img_paths: &[&Path], img_sizes: &[(usize, usize)]) -> Self {
-        let full_height = img_sizes[0].1;
-        let full_width = img_sizes.iter().map(|(w, _)| *w).sum();
-        Self {
-            paths: img_paths.iter().map(PathBuf::from).collect(),
-            sizes: img_sizes.to_vec(),
-            full_width,
-            full_height,
-            img_cache: HashMap::default(),
+/// Tile each panorama given after a --panorama marker into `ID.dzi`,
+/// next to a collection.dzc file referencing all of them.
+///
+/// Only the collection descriptor is generated, not the collection thumbnail tiles,
+/// which is why it declares a MaxLevel of 0.
+fn run_collection(c: &seahorse::Context) -> anyhow::Result<()> {
+    // Split the arguments into panoramas, each one starting with --panorama.
+    let mut panoramas: Vec<Vec<&Path>> = Vec::new();
+    for arg in &c.args {
+        if arg == "--panorama" {
+            panoramas.push(Vec::new());
+        } else if let Some(panorama) = panoramas.last_mut() {
+            panorama.push(Path::new(arg));
+        } else {
+            anyhow::bail!("{} does not follow a --panorama flag", arg);
         }
     }
-    fn extract(
-        &mut self,
-        img_height: usize,
-        tile_size: usize,
-        tx: usize,
-        ty: usize,
-    ) -> anyhow::Result<RgbImage> {
-        let left = tx * tile_size;
-        let top = ty * tile_size;
-        let right = (left + tile_size).min(self.full_width);
-        let bottom = (top + tile_size).min(self.full_height);
-
-        // Initialize an RgbImage of the correct size.
-        let tile_width = tile_size.min(right - left) as u32;
-        let tile_height = tile_size.min(bottom - top) as u32;
-        let mut img_tile = RgbImage::new(tile_width, tile_height);
-
-        // Identify the images that need to be loaded.
-        // Clear all images from the cache that are not in that list.
-        // Load images that are not loaded yet in the cache.
-        let mut accum_left = 0;
-        let crop = |img: RgbImage| crop_imm(&img, 0, 0, img.width(), img_height as u32).to_image();
-        for (id, (w, _)) in self.sizes.iter().enumerate() {
-            if left <= accum_left + w {
-                eprintln!("Using image {} for tile ({}, {})", id, tx, ty);
-                // load the image if not
-                let img: &RgbImage = match self.img_cache.entry(id) {
-                    Entry::Occupied(o) => o.into_mut(),
-                    Entry::Vacant(v) => v.insert(crop(
-                        ImageReader::open(&self.paths[id])?.decode()?.into_rgb8(),
-                    )),
-                };
-                // copy the correct view
-                let inner_left = (left as i64 - accum_left as i64).max(0) as u32;
-                let inner_right = (right - accum_left).min(*w) as u32;
-                let inner_width = inner_right - inner_left;
-                let img_view = img.view(inner_left, top as u32, inner_width, (bottom - top) as u32);
-
-                let tile_inner_left = (accum_left as i64 - left as i64).max(0) as u32;
-                img_tile.copy_from(&img_view, tile_inner_left, 0)?;
-            } else if self.img_cache.contains_key(&id) {
-                // unload the image if it was loaded
-                self.img_cache.remove(&id);
+    if panoramas.is_empty() {
+        anyhow::bail!("At least one --panorama is needed");
+    }
+    if let Some(id) = panoramas.iter().position(|p| p.is_empty()) {
+        anyhow::bail!("Panorama {} has no input image", id);
+    }
+    configure_threads(c)?;
+    let settings = settings(c, None)?;
+
+    let dzc_output_path = PathBuf::from(
+        c.string_flag("output")
+            .unwrap_or_else(|_| DEFAULT_COLLECTION_OUTPUT.to_string()),
+    );
+    if dzc_output_path.extension() != Some(OsStr::new("dzc")) {
+        anyhow::bail!(
+            "The output {} is not a .dzc file",
+            dzc_output_path.display()
+        );
+    }
+    let output_dir = dzc_output_path
+        .parent()
+        .context("Output dzc has no parent directory")?;
+
+    let mut items = XmlElement::new("Items");
+    for (id, img_paths) in panoramas.iter().enumerate() {
+        let img_paths = &permute(img_paths, &settings.input_order.sort(img_paths));
+        let dzi_output_path = output_dir.join(format!("{}.dzi", id));
+        let offsets = vec![0; img_paths.len()];
+        let rotations = vec![Rotation::None; img_paths.len()];
+        let (width, height) = tile_panorama(
+            img_paths,
+            &offsets,
+            &rotations,
+            None,
+            &[],
+            &dzi_output_path,
+            &settings,
+        )?;
+        let size = XmlElement::new("Size")
+            .attribute("Width", width)
+            .attribute("Height", height);
+        let item = XmlElement::new("I")
+            .attribute("Id", id)
+            .attribute("N", id)
+            .attribute("Source", format!("{}.dzi", id))
+            .child(size);
+        items = items.child(item);
+    }
+
+    let collection = XmlElement::new("Collection")
+        .attribute("xmlns", DEEPZOOM_NAMESPACE)
+        .attribute("MaxLevel", 0)
+        .attribute("TileSize", settings.config.tile_size)
+        .attribute("Format", &settings.encoding.format)
+        .attribute("NextItemId", panoramas.len())
+        .child(items);
+    std::fs::write(&dzc_output_path, collection.to_document()).context("Failed to write dzc file")
+}
+
+/// Verify the tilesets of the given .dzi files, reporting every problematic tile.
+fn run_verify(c: &seahorse::Context) -> anyhow::Result<()> {
+    if c.args.is_empty() {
+        anyhow::bail!("At least one .dzi file is needed");
+    }
+    let names = match c.string_flag("tile-name-template") {
+        Ok(template) => template.parse().context("Invalid --tile-name-template")?,
+        Err(_) => TileNameTemplate::default(),
+    };
+    let mut problem_count = 0;
+    for dzi_path in c.args.iter().map(Path::new) {
+        let xml = std::fs::read_to_string(dzi_path)
+            .with_context(|| format!("Failed to read {}", dzi_path.display()))?;
+        let dzi = DziInfo::parse(&xml)
+            .with_context(|| format!("Invalid dzi file {}", dzi_path.display()))?;
+        let tiles_dir = match &dzi.url {
+            Some(url) => dzi_path.with_file_name(url),
+            None => files_dir(dzi_path, DEFAULT_FILES_SUFFIX),
+        };
+        // Only the levels kept by the build are expected,
+        // and tiles of the skip color are missing on purpose.
+        let info = BuildInfo::load(&build_info_path(dzi_path))?;
+        let levels = match info.as_ref().and_then(|info| info.levels.clone()) {
+            Some(levels) => levels,
+            None => 0..=base_level(dzi.width, dzi.height),
+        };
+        let mut skipped_tiles = info.map(|info| info.skipped_tiles).unwrap_or_default();
+        skipped_tiles.sort_unstable();
+        let mut problems = verify_tiles(&dzi, &tiles_dir, &names, levels);
+        problems.retain(|(level, tx, ty, problem)| {
+            *problem != TileProblem::Missing
+                || skipped_tiles.binary_search(&(*level, *tx, *ty)).is_err()
+        });
+        for (level, tx, ty, problem) in &problems {
+            eprintln!(
+                "{}: tile ({}, {}) of level {}: {}",
+                dzi_path.display(),
+                tx,
+                ty,
+                level,
+                problem
+            );
+        }
+        problem_count += problems.len();
+
+        // Compare the tiles with the hashes recorded when they were generated.
+        if c.bool_flag("checksums") {
+            let dzi_dir = dzi_path.parent().unwrap_or_else(|| Path::new(""));
+            let checksums_path = dzi_dir.join(CHECKSUMS_FILE);
+            let checksums = Checksums::load(&checksums_path)?;
+            let prefix = tiles_dir.strip_prefix(dzi_dir).unwrap_or(&tiles_dir);
+            if !checksums
+                .entries
+                .keys()
+                .any(|f| Path::new(f).starts_with(prefix))
+            {
+                anyhow::bail!(
+                    "{} has no checksums of {}",
+                    checksums_path.display(),
+                    tiles_dir.display()
+                );
             }
-            accum_left += w;
-            if accum_left >= right {
-                break;
+            let mismatches = checksums.verify(dzi_dir, prefix);
+            for (file, problem) in &mismatches {
+                eprintln!("{}: {}: {}", dzi_path.display(), file, problem);
             }
+            problem_count += mismatches.len();
         }
+    }
+    if problem_count > 0 {
+        anyhow::bail!("{} tiles are missing or corrupt", problem_count);
+    }
+    eprintln!("All tiles are valid");
+    Ok(())
+}
 
-        Ok(img_tile)
+/// Serve the directory of a .dzi file, with a viewer page at the root.
+fn run_serve(c: &seahorse::Context) -> anyhow::Result<()> {
+    let dzi_path = match &c.args[..] {
+        [dzi] => Path::new(dzi),
+        _ => anyhow::bail!("Expected a single .dzi file"),
+    };
+    if !dzi_path.is_file() {
+        anyhow::bail!("{} does not exist", dzi_path.display());
     }
+    let port = c.int_flag("port").unwrap_or(DEFAULT_PORT);
+    let port = u16::try_from(port).context("Invalid --port")?;
+    let server = PreviewServer::bind(dzi_path, SocketAddr::from(([127, 0, 0, 1], port)))?;
+    eprintln!(
+        "Serving {} at http://{}/",
+        dzi_path.display(),
+        server.local_addr()?
+    );
+    server.run()
 }
 
-/// Compute the number of levels required for a given amount of tiles.
-fn levels_for(n: usize) -> usize {
-    assert!(n > 0);
-    if n == 1 {
-        1
-    } else {
-        let l2 = log_2(n);
-        if n % (2_usize.pow(l2 as u32)) != 0 {
-            l2 + 2
-        } else {
-            l2 + 1
-        }
+/// Parse a START..END range.
+fn parse_range(range: &str) -> anyhow::Result<Range<usize>> {
+    let (start, end) = range.split_once("..").context("Expected START..END")?;
+    Ok(start.trim().parse()?..end.trim().parse()?)
+}
+
+/// Parse a color in the RRGGBB hexadecimal format.
+fn parse_color(hex: &str) -> anyhow::Result<Rgb<u8>> {
+    let hex = hex.trim_start_matches('#');
+    if hex.len() != 6 || !hex.is_ascii() {
+        anyhow::bail!("{} is not a RRGGBB color", hex);
     }
+    let channel = |i: usize| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16);
+    Ok(Rgb([channel(0)?, channel(1)?, channel(2)?]))
 }
 
-fn log_2(x: usize) -> usize {
-    num_bits::<usize>() - x.leading_zeros() as usize - 1
+/// Parse LEFT,RIGHT pairs of edge columns separated by `;`.
+fn parse_trim_edges(list: &str) -> anyhow::Result<Vec<(usize, usize)>> {
+    list.split(';')
+        .map(|edges| match parse_list(edges)?[..] {
+            [left, right] => Ok((left, right)),
+            _ => anyhow::bail!("{} is not LEFT,RIGHT", edges),
+        })
+        .collect::<anyhow::Result<_>>()
+        .context("Invalid --trim-edges")
 }
 
-const fn num_bits<T>() -> usize {
-    std::mem::size_of::<T>() * 8
+/// Parse a comma separated list of values.
+fn parse_list<T: FromStr>(list: &str) -> anyhow::Result<Vec<T>>
+where
+    anyhow::Error: From<T::Err>,
+{
+    list.split(',')
+        .map(|item| {
+            item.trim()
+                .parse()
+                .map_err(anyhow::Error::from)
+                .with_context(|| format!("Invalid value {}", item))
+        })
+        .collect()
 }
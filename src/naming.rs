@@ -0,0 +1,84 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use std::str::FromStr;
+
+/// Template of the tile file names inside each level directory.
+///
+/// Placeholders are `{x}` and `{y}` for the tile coordinates, `{z}` for the level
+/// and `{ext}` for the file extension. Numbers can be zero-padded with `{x:04}`.
+/// The DeepZoom default is `{x}_{y}.{ext}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TileNameTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    X(usize),
+    Y(usize),
+    Z(usize),
+    Ext,
+}
+
+impl Default for TileNameTemplate {
+    fn default() -> Self {
+        "{x}_{y}.{ext}".parse().unwrap()
+    }
+}
+
+impl FromStr for TileNameTemplate {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .map(|end| start + end)
+                .ok_or_else(|| anyhow::anyhow!("Unclosed placeholder in {}", s))?;
+            let (name, padding) = match rest[start + 1..end].split_once(':') {
+                Some((name, padding)) => (name, padding.parse()?),
+                None => (&rest[start + 1..end], 0),
+            };
+            parts.push(match name {
+                "x" => Part::X(padding),
+                "y" => Part::Y(padding),
+                "z" => Part::Z(padding),
+                "ext" => Part::Ext,
+                _ => anyhow::bail!("Unknown placeholder {{{}}} in {}", name, s),
+            });
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_string()));
+        }
+        // Without both coordinates, tiles of a level would overwrite each other.
+        let has_x = parts.iter().any(|part| matches!(part, Part::X(_)));
+        let has_y = parts.iter().any(|part| matches!(part, Part::Y(_)));
+        if !has_x || !has_y {
+            anyhow::bail!("The template {} needs both {{x}} and {{y}} placeholders", s);
+        }
+        Ok(Self { parts })
+    }
+}
+
+impl TileNameTemplate {
+    /// File name of the tile (tx, ty) of a level.
+    pub fn render(&self, level: usize, tx: usize, ty: usize, extension: &str) -> String {
+        let mut name = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => name += literal,
+                Part::X(padding) => name += &format!("{:0width$}", tx, width = padding),
+                Part::Y(padding) => name += &format!("{:0width$}", ty, width = padding),
+                Part::Z(padding) => name += &format!("{:0width$}", level, width = padding),
+                Part::Ext => name += extension,
+            }
+        }
+        name
+    }
+}
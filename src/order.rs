@@ -0,0 +1,96 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use rayon::prelude::*;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// Order in which the tiles of each level are generated, and given to the sink.
+/// The tiles are the same whatever the order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TileOrder {
+    /// Column after column from the left, each from top to bottom.
+    /// Columns are generated in parallel, so this is also the fastest order.
+    #[default]
+    Column,
+    /// Row after row from the top, each from left to right.
+    Row,
+    /// Rings around the center of the level, each clockwise from the top.
+    Spiral,
+    /// Closest tiles to the center of the level first, for viewers loading the middle first.
+    CenterOut,
+}
+
+impl FromStr for TileOrder {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "column" => Ok(Self::Column),
+            "row" => Ok(Self::Row),
+            "spiral" => Ok(Self::Spiral),
+            "center-out" => Ok(Self::CenterOut),
+            _ => anyhow::bail!(
+                "{} is not a tile order, expected column, row, spiral or center-out",
+                s
+            ),
+        }
+    }
+}
+
+impl TileOrder {
+    /// Coordinates of the tiles of the given columns and rows, in this order.
+    /// The center is the one of the area covered by these tiles.
+    pub fn sort(self, columns: &[usize], rows: Range<usize>) -> Vec<(usize, usize)> {
+        let mut tiles: Vec<(usize, usize)> = columns
+            .iter()
+            .flat_map(|&tx| rows.clone().map(move |ty| (tx, ty)))
+            .collect();
+        let (first_column, last_column) = match (columns.first(), columns.last()) {
+            (Some(&first), Some(&last)) => (first, last),
+            _ => return tiles,
+        };
+        // Offsets to the center in half tiles, to stay integers with an even number of tiles.
+        let offsets = |(tx, ty): (usize, usize)| {
+            let dx = (2 * tx) as i64 - (first_column + last_column) as i64;
+            let dy = (2 * ty) as i64 - (rows.start + rows.end - 1) as i64;
+            (dx, dy)
+        };
+        match self {
+            Self::Column => (),
+            Self::Row => tiles.sort_by_key(|&(tx, ty)| (ty, tx)),
+            Self::CenterOut => tiles.sort_by_key(|&tile| {
+                let (dx, dy) = offsets(tile);
+                dx * dx + dy * dy
+            }),
+            Self::Spiral => tiles.sort_by(|&a, &b| {
+                let ring_and_angle = |tile| {
+                    let (dx, dy) = offsets(tile);
+                    // Clockwise angle from the top, rows going down.
+                    let angle = (dx as f64).atan2(-dy as f64);
+                    let angle = if angle < 0.0 {
+                        angle + std::f64::consts::TAU
+                    } else {
+                        angle
+                    };
+                    (dx.abs().max(dy.abs()), angle)
+                };
+                let (ring_a, angle_a) = ring_and_angle(a);
+                let (ring_b, angle_b) = ring_and_angle(b);
+                ring_a.cmp(&ring_b).then(angle_a.total_cmp(&angle_b))
+            }),
+        }
+        tiles
+    }
+}
+
+/// Run `f` on each tile, following their order as far as parallelism allows:
+/// tiles are processed in batches of the size of the current rayon thread pool,
+/// one batch after the other.
+pub fn for_each_in_order<F>(tiles: &[(usize, usize)], f: F) -> anyhow::Result<()>
+where
+    F: Fn(usize, usize) -> anyhow::Result<()> + Sync,
+{
+    for batch in tiles.chunks(rayon::current_num_threads()) {
+        batch.par_iter().try_for_each(|&(tx, ty)| f(tx, ty))?;
+    }
+    Ok(())
+}
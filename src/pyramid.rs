@@ -0,0 +1,205 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::order::{self, TileOrder};
+use crate::{Tile, TilePixel, TileSink, TilingConfig};
+use anyhow::Context;
+use rayon::prelude::*;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// How the 2x2 blocks of pixels of a level are reduced to one pixel of the next one,
+/// channel by channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DownsampleMode {
+    /// Average of the pixels, for photographs.
+    #[default]
+    Mean,
+    /// Median of the pixels, keeping edges sharper than the mean.
+    Median,
+    /// Darkest value, keeping dark lines on a light background visible, like on maps.
+    Darkest,
+    /// Lightest value, keeping light lines on a dark background visible.
+    Lightest,
+}
+
+impl FromStr for DownsampleMode {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mean" => Ok(Self::Mean),
+            "median" => Ok(Self::Median),
+            "darkest" => Ok(Self::Darkest),
+            "lightest" => Ok(Self::Lightest),
+            _ => anyhow::bail!(
+                "{} is not a downsample mode, expected mean, median, darkest or lightest",
+                s
+            ),
+        }
+    }
+}
+
+impl DownsampleMode {
+    /// Reduce the values of a channel of up to 4 pixels, 0 if there is none.
+    fn reduce(self, values: &mut [u8]) -> u8 {
+        let count = values.len() as u16;
+        if count == 0 {
+            return 0;
+        }
+        match self {
+            Self::Mean => {
+                let sum: u16 = values.iter().map(|&v| v as u16).sum();
+                // Round to nearest, truncating would darken each level a bit more.
+                ((sum + count / 2) / count) as u8
+            }
+            Self::Median => {
+                values.sort_unstable();
+                let mid = values.len() / 2;
+                if values.len() % 2 == 1 {
+                    values[mid]
+                } else {
+                    ((values[mid - 1] as u16 + values[mid] as u16).div_ceil(2)) as u8
+                }
+            }
+            Self::Darkest => *values.iter().min().unwrap(),
+            Self::Lightest => *values.iter().max().unwrap(),
+        }
+    }
+}
+
+/// Compute half resolution images of the given columns and rows
+/// and output the number of tiles of the new level.
+/// Tiles are computed in parallel, in the tile order of the config.
+pub fn compute_half_resolutions<P: TilePixel, S: TileSink<P>>(
+    sink: &S,
+    previous_lvl: usize,
+    tile_count_width: usize,
+    tile_count_height: usize,
+    columns: &[usize],
+    rows: Range<usize>,
+    config: &TilingConfig,
+) -> anyhow::Result<(usize, usize)> {
+    let half_tile_count_width = tile_count_width.div_ceil(2);
+    let half_tile_count_height = tile_count_height.div_ceil(2);
+    let grid = (tile_count_width, tile_count_height);
+    let write_half_tile = |tx, ty| {
+        let half_img = half_res_tile(sink, previous_lvl, grid, tx, ty, config.downsample)?;
+        sink.write_tile(previous_lvl - 1, tx, ty, &half_img)
+    };
+    match config.tile_order {
+        TileOrder::Column => columns.par_iter().try_for_each(|&tx| {
+            for ty in rows.clone() {
+                write_half_tile(tx, ty)?;
+            }
+            anyhow::Ok(())
+        })?,
+        order => order::for_each_in_order(&order.sort(columns, rows), write_half_tile)?,
+    }
+    Ok((half_tile_count_width, half_tile_count_height))
+}
+
+/// Downsample the 2x2 block of tiles of the previous level covered by the tile (tx, ty),
+/// in the previous level grid of the given number of columns and rows.
+fn half_res_tile<P: TilePixel, S: TileSink<P>>(
+    sink: &S,
+    previous_lvl: usize,
+    (columns, rows): (usize, usize),
+    tx: usize,
+    ty: usize,
+    mode: DownsampleMode,
+) -> anyhow::Result<Tile<P>> {
+    let top_left: Tile<P> = sink
+        .read_tile(previous_lvl, tx * 2, ty * 2)?
+        .with_context(|| {
+            format!(
+                "Missing tile ({}, {}) of level {}",
+                tx * 2,
+                ty * 2,
+                previous_lvl
+            )
+        })?;
+    // Tiles past the right and bottom edges of the level are replaced by empty tiles,
+    // and the layout of the 2x2 block is derived from the sizes of the loaded ones.
+    // They are not read, since a previous build of a larger panorama may have left some.
+    let read_or_empty = |x, y| -> anyhow::Result<Tile<P>> {
+        if x >= columns || y >= rows {
+            return Ok(Tile::default());
+        }
+        Ok(sink.read_tile(previous_lvl, x, y)?.unwrap_or_default())
+    };
+    let top_right = read_or_empty(tx * 2 + 1, ty * 2)?;
+    let bottom_left = read_or_empty(tx * 2, ty * 2 + 1)?;
+    let bottom_right = read_or_empty(tx * 2 + 1, ty * 2 + 1)?;
+    Ok(half_res(
+        top_left,
+        top_right,
+        bottom_left,
+        bottom_right,
+        mode,
+    ))
+}
+
+/// Reduce the 2x2 blocks of pixels of four tiles assembled in a square.
+/// Tiles whose sizes disagree with their neighbors are padded instead of failing:
+/// each column and row of tiles is as large as its largest tile,
+/// and pixels not covered by any tile are black.
+pub fn half_res<P: TilePixel>(
+    top_left: Tile<P>,
+    top_right: Tile<P>,
+    bottom_left: Tile<P>,
+    bottom_right: Tile<P>,
+    mode: DownsampleMode,
+) -> Tile<P> {
+    let left_width = top_left.width().max(bottom_left.width());
+    let top_height = top_left.height().max(top_right.height());
+    let full_width = left_width + top_right.width().max(bottom_right.width());
+    let full_height = top_height + bottom_left.height().max(bottom_right.height());
+    let half_width = full_width.div_ceil(2);
+    let half_height = full_height.div_ceil(2);
+
+    // Helper closure to use the correct image to retrieve a given pixel.
+    let img_source_and_offset = |x, y| {
+        if x < left_width && y < top_height {
+            (&top_left, 0, 0)
+        } else if x < left_width {
+            (&bottom_left, 0, top_height)
+        } else if y < top_height {
+            (&top_right, left_width, 0)
+        } else {
+            (&bottom_right, left_width, top_height)
+        }
+    };
+    // Helper closure to extract the correct pixel from the correct image.
+    let extract_pixel = |target_x, target_y| {
+        let (img, offset_x, offset_y) = img_source_and_offset(target_x, target_y);
+        let local_x = target_x - offset_x;
+        let local_y = target_y - offset_y;
+        if local_x >= img.width() || local_y >= img.height() {
+            None
+        } else {
+            Some(*img.get_pixel(local_x, local_y))
+        }
+    };
+    Tile::from_fn(half_width, half_height, |x, y| {
+        let pixels = [
+            extract_pixel(2 * x, 2 * y),
+            extract_pixel(2 * x + 1, 2 * y),
+            extract_pixel(2 * x, 2 * y + 1),
+            extract_pixel(2 * x + 1, 2 * y + 1),
+        ];
+        // Gather each channel of the 4 subpixels (maybe less if near a border)
+        let mut channels = [[0; 4]; 4];
+        let mut pix_count = 0;
+        for pix in IntoIterator::into_iter(pixels).flatten() {
+            for (values, &channel) in channels.iter_mut().zip(pix.channels()) {
+                values[pix_count] = channel;
+            }
+            pix_count += 1;
+        }
+        // Padding pixels not covered by any tile stay black.
+        let mut reduced = [0; 4];
+        for (r, values) in reduced.iter_mut().zip(&mut channels) {
+            *r = mode.reduce(&mut values[..pix_count]);
+        }
+        *P::from_slice(&reduced[..P::CHANNEL_COUNT as usize])
+    })
+}
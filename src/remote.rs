@@ -0,0 +1,16 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use std::path::Path;
+
+/// Check if an input image is given as an HTTP(S) URL instead of a local path.
+pub fn is_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|s| s.starts_with("http://") || s.starts_with("https://"))
+}
+
+/// Download the whole content of an input image.
+#[cfg(feature = "http")]
+pub fn download(url: &str) -> anyhow::Result<Vec<u8>> {
+    let response = reqwest::blocking::get(url)?.error_for_status()?;
+    Ok(response.bytes()?.to_vec())
+}
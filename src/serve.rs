@@ -0,0 +1,177 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::Context;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+
+/// OpenSeadragon page showing the tileset of the given .dzi file, relative to the page.
+pub fn viewer_html(dzi_name: &str) -> String {
+    let title = dzi_name.replace('&', "&amp;").replace('<', "&lt;");
+    let source = dzi_name
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('<', "\\x3c");
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{0}</title>
+<script src="https://cdn.jsdelivr.net/npm/openseadragon@4.1/build/openseadragon/openseadragon.min.js"></script>
+<style>html, body, #viewer {{ margin: 0; width: 100%; height: 100%; background: black; }}</style>
+</head>
+<body>
+<div id="viewer"></div>
+<script>
+OpenSeadragon({{
+  id: "viewer",
+  prefixUrl: "https://cdn.jsdelivr.net/npm/openseadragon@4.1/build/openseadragon/images/",
+  tileSources: "{1}",
+}});
+</script>
+</body>
+</html>
+"#,
+        title, source
+    )
+}
+
+/// Minimal HTTP server previewing a tileset, since browsers cannot load tiles from `file://`.
+/// It serves the files of the directory of the .dzi file, and a viewer page at `/`.
+pub struct PreviewServer {
+    listener: TcpListener,
+    root: PathBuf,
+    index: String,
+}
+
+impl PreviewServer {
+    pub fn bind(dzi_path: &Path, addr: SocketAddr) -> anyhow::Result<Self> {
+        let dzi_name = dzi_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .context("Invalid .dzi file name")?;
+        let root = match dzi_path.parent() {
+            Some(parent) if parent != Path::new("") => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let listener =
+            TcpListener::bind(addr).with_context(|| format!("Failed to listen on {}", addr))?;
+        Ok(Self {
+            listener,
+            root,
+            index: viewer_html(dzi_name),
+        })
+    }
+
+    pub fn local_addr(&self) -> anyhow::Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Answer requests until the process is stopped, each connection in its own thread.
+    pub fn run(self) -> anyhow::Result<()> {
+        let server = &self;
+        std::thread::scope(|scope| {
+            for stream in server.listener.incoming() {
+                let stream = stream.context("Failed to accept a connection")?;
+                scope.spawn(move || {
+                    if let Err(err) = server.answer(stream) {
+                        eprintln!("Failed to answer a request: {}", err);
+                    }
+                });
+            }
+            Ok(())
+        })
+    }
+
+    fn answer(&self, mut stream: TcpStream) -> anyhow::Result<()> {
+        let mut reader = BufReader::new(&stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // Skip the headers, up to the empty line.
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 0 && header.trim_end() != "" {
+            header.clear();
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+        let path = target.split(['?', '#']).next().unwrap_or("");
+        let (status, content_type, body) = match method {
+            "GET" | "HEAD" => self.resource(path),
+            _ => (
+                "405 Method Not Allowed",
+                "text/plain",
+                b"Method not allowed".to_vec(),
+            ),
+        };
+        eprintln!("{} {} {}", method, target, status);
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            content_type,
+            body.len()
+        )?;
+        if method != "HEAD" {
+            stream.write_all(&body)?;
+        }
+        Ok(())
+    }
+
+    /// Status, content type and content of the resource at the given URL path.
+    fn resource(&self, path: &str) -> (&'static str, &'static str, Vec<u8>) {
+        let not_found = ("404 Not Found", "text/plain", b"Not found".to_vec());
+        if path == "/" || path == "/index.html" {
+            return (
+                "200 OK",
+                "text/html; charset=utf-8",
+                self.index.clone().into_bytes(),
+            );
+        }
+        let relative = match percent_decode(path.trim_start_matches('/')) {
+            Some(relative) => PathBuf::from(relative),
+            None => return not_found,
+        };
+        // Never serve files outside of the root directory.
+        if !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            return not_found;
+        }
+        match std::fs::read(self.root.join(&relative)) {
+            Ok(content) => ("200 OK", content_type(&relative), content),
+            Err(_) => not_found,
+        }
+    }
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("dzi") | Some("dzc") | Some("xml") => "application/xml",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("png") => "image/png",
+        Some("avif") => "image/avif",
+        Some("html") => "text/html; charset=utf-8",
+        Some("json") => "application/json",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Decode the %XX escapes of an URL path, or return None if they are invalid.
+fn percent_decode(path: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
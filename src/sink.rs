@@ -0,0 +1,364 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::checksums::sha256_hex;
+use crate::encoding::{encode_tile_within_budget, TileEncoding};
+use crate::{OpenFilesLimit, Tile, TileNameTemplate, TilePixel};
+use anyhow::Context;
+use image::io::Reader as ImageReader;
+use image::Rgb;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Destination of the generated tiles.
+///
+/// Coarser levels are computed from the tiles of the previous level,
+/// so a sink must be able to give back the tiles that were written to it.
+/// Tiles are generated in parallel, so the sink is shared between threads.
+pub trait TileSink<P: TilePixel>: Sync {
+    /// Store the tile at coordinates (tx, ty) of the given level.
+    fn write_tile(&self, level: usize, tx: usize, ty: usize, tile: &Tile<P>) -> anyhow::Result<()>;
+
+    /// Retrieve a previously written tile, or `None` if there is none at these coordinates.
+    fn read_tile(&self, level: usize, tx: usize, ty: usize) -> anyhow::Result<Option<Tile<P>>>;
+
+    /// Delete all the tiles of a level, once they are not needed anymore.
+    /// Sinks that cannot delete tiles keep them.
+    fn discard_level(&self, _level: usize) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Delete the tile at coordinates (tx, ty) of the given level, if there is one.
+    /// Sinks that cannot delete tiles keep it.
+    fn remove_tile(&self, _level: usize, _tx: usize, _ty: usize) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Tile sink writing every tile to several sinks,
+/// like the same tiles encoded with different qualities.
+/// Tiles are only read back from the first sink.
+pub struct MultiTileSink<S> {
+    sinks: Vec<S>,
+}
+
+impl<S> MultiTileSink<S> {
+    pub fn new(sinks: Vec<S>) -> Self {
+        assert!(!sinks.is_empty(), "At least one sink is needed");
+        Self { sinks }
+    }
+
+    pub fn sinks(&self) -> &[S] {
+        &self.sinks
+    }
+}
+
+impl<P: TilePixel, S: TileSink<P>> TileSink<P> for MultiTileSink<S> {
+    fn write_tile(&self, level: usize, tx: usize, ty: usize, tile: &Tile<P>) -> anyhow::Result<()> {
+        for sink in &self.sinks {
+            sink.write_tile(level, tx, ty, tile)?;
+        }
+        Ok(())
+    }
+
+    fn read_tile(&self, level: usize, tx: usize, ty: usize) -> anyhow::Result<Option<Tile<P>>> {
+        self.sinks[0].read_tile(level, tx, ty)
+    }
+
+    fn discard_level(&self, level: usize) -> anyhow::Result<()> {
+        for sink in &self.sinks {
+            sink.discard_level(level)?;
+        }
+        Ok(())
+    }
+
+    fn remove_tile(&self, level: usize, tx: usize, ty: usize) -> anyhow::Result<()> {
+        for sink in &self.sinks {
+            sink.remove_tile(level, tx, ty)?;
+        }
+        Ok(())
+    }
+}
+
+/// Tile sink skipping the tiles entirely of a given color, like areas without data,
+/// so that viewers show their own background there instead.
+/// Skipped tiles read back as tiles of that color, to build the coarser levels,
+/// and the coarser tiles of that color are skipped too.
+pub struct SkipColorSink<S> {
+    sink: S,
+    color: Rgb<u8>,
+    tolerance: u8,
+    /// Sizes of the skipped tiles.
+    skipped: Mutex<HashMap<TileCoords, (u32, u32)>>,
+}
+
+/// Level and coordinates of a tile.
+type TileCoords = (usize, usize, usize);
+
+impl<S> SkipColorSink<S> {
+    /// Skip the tiles whose channels all differ from the color by at most the tolerance.
+    pub fn new(sink: S, color: Rgb<u8>, tolerance: u8) -> Self {
+        Self {
+            sink,
+            color,
+            tolerance,
+            skipped: Mutex::default(),
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.sink
+    }
+
+    /// Number of tiles skipped so far.
+    pub fn skipped(&self) -> usize {
+        self.skipped.lock().unwrap().len()
+    }
+
+    /// Level and coordinates of the tiles skipped so far, in order.
+    pub fn skipped_tiles(&self) -> Vec<(usize, usize, usize)> {
+        let mut tiles: Vec<_> = self.skipped.lock().unwrap().keys().copied().collect();
+        tiles.sort_unstable();
+        tiles
+    }
+}
+
+impl<P: TilePixel, S: TileSink<P>> TileSink<P> for SkipColorSink<S> {
+    fn write_tile(&self, level: usize, tx: usize, ty: usize, tile: &Tile<P>) -> anyhow::Result<()> {
+        let color = P::from_rgb(self.color);
+        let skip = tile.pixels().all(|pixel| {
+            let mut channels = pixel.channels().iter().zip(color.channels());
+            channels.all(|(&a, &b)| a.abs_diff(b) <= self.tolerance)
+        });
+        let key = (level, tx, ty);
+        if skip {
+            self.skipped.lock().unwrap().insert(key, tile.dimensions());
+            // Remove the tile of a previous build, which the viewer would show instead.
+            self.sink.remove_tile(level, tx, ty)
+        } else {
+            self.skipped.lock().unwrap().remove(&key);
+            self.sink.write_tile(level, tx, ty, tile)
+        }
+    }
+
+    fn read_tile(&self, level: usize, tx: usize, ty: usize) -> anyhow::Result<Option<Tile<P>>> {
+        let size = self.skipped.lock().unwrap().get(&(level, tx, ty)).copied();
+        match size {
+            Some((width, height)) => Ok(Some(Tile::from_pixel(
+                width,
+                height,
+                P::from_rgb(self.color),
+            ))),
+            None => self.sink.read_tile(level, tx, ty),
+        }
+    }
+
+    fn discard_level(&self, level: usize) -> anyhow::Result<()> {
+        self.sink.discard_level(level)
+    }
+
+    fn remove_tile(&self, level: usize, tx: usize, ty: usize) -> anyhow::Result<()> {
+        self.sink.remove_tile(level, tx, ty)
+    }
+}
+
+/// Time waited before trying again a failed tile write, doubled for each other retry.
+const FIRST_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Tile sink saving images in a directory, with the deepzoom layout `level/tx_ty.extension`
+/// unless another tile name template is given.
+pub struct FsTileSink {
+    dir: PathBuf,
+    encoding: TileEncoding,
+    names: TileNameTemplate,
+    open_files: Option<Arc<OpenFilesLimit>>,
+    /// SHA-256 hashes of the written tiles, by path, if recorded.
+    checksums: Option<Mutex<BTreeMap<PathBuf, String>>>,
+    /// Number of times a failed tile write is tried again.
+    io_retries: usize,
+    written_tiles: AtomicUsize,
+    written_bytes: AtomicU64,
+    over_budget_tiles: AtomicUsize,
+}
+
+impl FsTileSink {
+    pub fn new<D: Into<PathBuf>>(dir: D, encoding: TileEncoding) -> Self {
+        Self {
+            dir: dir.into(),
+            encoding,
+            names: TileNameTemplate::default(),
+            open_files: None,
+            checksums: None,
+            io_retries: 0,
+            written_tiles: AtomicUsize::new(0),
+            written_bytes: AtomicU64::new(0),
+            over_budget_tiles: AtomicUsize::new(0),
+        }
+    }
+
+    /// Number of tiles and bytes written so far.
+    pub fn written(&self) -> (usize, u64) {
+        (
+            self.written_tiles.load(Ordering::Relaxed),
+            self.written_bytes.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Number of tiles written so far that do not fit in the size budget of the encoding,
+    /// even with the lowest quality.
+    pub fn over_budget(&self) -> usize {
+        self.over_budget_tiles.load(Ordering::Relaxed)
+    }
+
+    /// Name the tiles of each level directory with the given template.
+    pub fn with_tile_names(self, names: TileNameTemplate) -> Self {
+        Self { names, ..self }
+    }
+
+    /// Bound the number of tile files opened at once, possibly shared with other sinks.
+    pub fn with_open_files_limit(self, limit: Arc<OpenFilesLimit>) -> Self {
+        Self {
+            open_files: Some(limit),
+            ..self
+        }
+    }
+
+    /// Try again failed tile writes up to the given number of times,
+    /// waiting twice longer each time, for storage with transient errors.
+    pub fn with_io_retries(self, io_retries: usize) -> Self {
+        Self { io_retries, ..self }
+    }
+
+    /// Record the SHA-256 hash of every written tile, computed on the encoded bytes.
+    pub fn with_checksums(self) -> Self {
+        Self {
+            checksums: Some(Mutex::default()),
+            ..self
+        }
+    }
+
+    /// Hashes of the tiles written so far and not discarded, by path, if recorded.
+    pub fn checksums(&self) -> Option<BTreeMap<PathBuf, String>> {
+        let checksums = self.checksums.as_ref()?;
+        Some(checksums.lock().unwrap().clone())
+    }
+
+    fn tile_path(&self, level: usize, tx: usize, ty: usize) -> PathBuf {
+        img_out_path(&self.dir, &self.names, &self.encoding.format, level, tx, ty)
+    }
+}
+
+impl<P: TilePixel> TileSink<P> for FsTileSink {
+    fn write_tile(&self, level: usize, tx: usize, ty: usize, tile: &Tile<P>) -> anyhow::Result<()> {
+        let path = self.tile_path(level, tx, ty);
+        let (bytes, fits) = encode_tile_within_budget(tile, &self.encoding)
+            .with_context(|| format!("Failed to encode tile {}", path.display()))?;
+        if !fits {
+            self.over_budget_tiles.fetch_add(1, Ordering::Relaxed);
+            eprintln!(
+                "BEWARE that tile {} has {} bytes, over the budget even with the lowest quality",
+                path.display(),
+                bytes.len()
+            );
+        }
+        let save = || -> std::io::Result<()> {
+            let _open_file = self.open_files.as_ref().map(|limit| limit.acquire());
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            std::fs::write(&path, &bytes)
+        };
+        let (mut retry, mut delay) = (0, FIRST_RETRY_DELAY);
+        loop {
+            match save() {
+                Ok(()) => break,
+                Err(err) if retry < self.io_retries => {
+                    retry += 1;
+                    eprintln!(
+                        "Failed to save tile {} ({}), retry {} of {} in {:?}",
+                        path.display(),
+                        err,
+                        retry,
+                        self.io_retries,
+                        delay
+                    );
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                }
+                Err(err) => {
+                    return Err(err).with_context(|| {
+                        format!(
+                            "Failed to save tile ({}, {}) of level {} to {}",
+                            tx,
+                            ty,
+                            level,
+                            path.display()
+                        )
+                    })
+                }
+            }
+        }
+        if let Some(checksums) = &self.checksums {
+            let hash = sha256_hex(&bytes);
+            checksums.lock().unwrap().insert(path, hash);
+        }
+        self.written_tiles.fetch_add(1, Ordering::Relaxed);
+        self.written_bytes
+            .fetch_add(bytes.len() as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn read_tile(&self, level: usize, tx: usize, ty: usize) -> anyhow::Result<Option<Tile<P>>> {
+        let path = self.tile_path(level, tx, ty);
+        let _open_file = self.open_files.as_ref().map(|limit| limit.acquire());
+        match ImageReader::open(&path) {
+            Ok(reader) => {
+                Ok(Some(P::from_dynamic(reader.decode().with_context(
+                    || format!("Failed to decode tile {}", path.display()),
+                )?)))
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn discard_level(&self, level: usize) -> anyhow::Result<()> {
+        let level_dir = self.dir.join(level.to_string());
+        if let Some(checksums) = &self.checksums {
+            let mut checksums = checksums.lock().unwrap();
+            checksums.retain(|path, _| !path.starts_with(&level_dir));
+        }
+        match std::fs::remove_dir_all(&level_dir) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                Err(err).with_context(|| format!("Failed to delete {}", level_dir.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn remove_tile(&self, level: usize, tx: usize, ty: usize) -> anyhow::Result<()> {
+        let path = self.tile_path(level, tx, ty);
+        if let Some(checksums) = &self.checksums {
+            checksums.lock().unwrap().remove(&path);
+        }
+        match std::fs::remove_file(&path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                Err(err).with_context(|| format!("Failed to delete tile {}", path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Image output path, in the directory of its level.
+fn img_out_path(
+    dir: &Path,
+    names: &TileNameTemplate,
+    extension: &str,
+    level: usize,
+    tx: usize,
+    ty: usize,
+) -> PathBuf {
+    dir.join(level.to_string())
+        .join(names.render(level, tx, ty, extension))
+}
@@ -0,0 +1,154 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Tagging of tiles as sRGB, which is what browsers assume but not consistently.
+
+use std::sync::OnceLock;
+
+/// ICC v2 display profile of the sRGB color space, or of its gray equivalent.
+pub fn icc_profile(gray: bool) -> &'static [u8] {
+    static RGB: OnceLock<Vec<u8>> = OnceLock::new();
+    static GRAY: OnceLock<Vec<u8>> = OnceLock::new();
+    if gray {
+        GRAY.get_or_init(|| build_icc_profile(true))
+    } else {
+        RGB.get_or_init(|| build_icc_profile(false))
+    }
+}
+
+/// Insert an sRGB chunk, with a perceptual rendering intent, in an encoded png.
+/// It must come before the image data, so it is placed right after the IHDR chunk.
+pub fn insert_png_chunk(png: &mut Vec<u8>) {
+    // 8 bytes of png signature, then the IHDR chunk of 13 bytes of data,
+    // plus 12 bytes of length, type and crc.
+    let after_ihdr = 8 + 13 + 12;
+    let mut chunk = Vec::with_capacity(13);
+    chunk.extend_from_slice(&1u32.to_be_bytes());
+    chunk.extend_from_slice(b"sRGB");
+    chunk.push(0);
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(&chunk[4..]);
+    let crc = crc.finalize();
+    chunk.extend_from_slice(&crc.to_be_bytes());
+    png.splice(after_ihdr..after_ihdr, chunk);
+}
+
+/// D50 illuminant of the profile connection space.
+const D50: [f64; 3] = [0.9642, 1.0, 0.8249];
+
+/// sRGB primaries, adapted to D50.
+const PRIMARIES: [[f64; 3]; 3] = [
+    [0.4361, 0.2225, 0.0139],
+    [0.3851, 0.7169, 0.0971],
+    [0.1431, 0.0606, 0.7141],
+];
+
+fn build_icc_profile(gray: bool) -> Vec<u8> {
+    // Tags data, as (signature, data).
+    let curve = curve_tag();
+    let mut tags: Vec<(&[u8; 4], Vec<u8>)> = vec![
+        (b"desc", desc_tag(if gray { "sRGB gray" } else { "sRGB" })),
+        (b"cprt", text_tag("No copyright, use freely")),
+        (b"wtpt", xyz_tag(D50)),
+    ];
+    if gray {
+        tags.push((b"kTRC", curve));
+    } else {
+        tags.push((b"rXYZ", xyz_tag(PRIMARIES[0])));
+        tags.push((b"gXYZ", xyz_tag(PRIMARIES[1])));
+        tags.push((b"bXYZ", xyz_tag(PRIMARIES[2])));
+        tags.push((b"rTRC", curve.clone()));
+        tags.push((b"gTRC", curve.clone()));
+        tags.push((b"bTRC", curve));
+    }
+
+    // Tag table, followed by the 4 bytes aligned data of each tag.
+    let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+    let mut data = Vec::new();
+    let data_start = 128 + 4 + 12 * tags.len();
+    let mut offsets: Vec<(&Vec<u8>, usize)> = Vec::new();
+    for (signature, tag) in &tags {
+        // Identical tags, like the three curves, share their data.
+        let offset = match offsets.iter().find(|(other, _)| *other == tag) {
+            Some((_, offset)) => *offset,
+            None => {
+                let offset = data_start + data.len();
+                data.extend_from_slice(tag);
+                data.resize(data.len().div_ceil(4) * 4, 0);
+                offsets.push((tag, offset));
+                offset
+            }
+        };
+        table.extend_from_slice(*signature);
+        table.extend_from_slice(&(offset as u32).to_be_bytes());
+        table.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+    }
+
+    let size = data_start + data.len();
+    let mut profile = Vec::with_capacity(size);
+    profile.extend_from_slice(&(size as u32).to_be_bytes());
+    profile.extend_from_slice(&[0; 4]); // preferred CMM
+    profile.extend_from_slice(&[2, 0x10, 0, 0]); // version 2.1
+    profile.extend_from_slice(b"mntr");
+    profile.extend_from_slice(if gray { b"GRAY" } else { b"RGB " });
+    profile.extend_from_slice(b"XYZ ");
+    profile.extend_from_slice(&[0; 12]); // creation date
+    profile.extend_from_slice(b"acsp");
+    profile.extend_from_slice(&[0; 24]); // platform, flags, manufacturer, model, attributes
+    profile.extend_from_slice(&[0; 4]); // perceptual rendering intent
+    profile.extend_from_slice(&xyz_number(D50));
+    profile.extend_from_slice(&[0; 4]); // creator
+    profile.resize(128, 0); // profile id and reserved
+    profile.extend_from_slice(&table);
+    profile.extend_from_slice(&data);
+    profile
+}
+
+fn s15_fixed16(value: f64) -> [u8; 4] {
+    ((value * 65536.0).round() as i32).to_be_bytes()
+}
+
+fn xyz_number(xyz: [f64; 3]) -> Vec<u8> {
+    xyz.iter().flat_map(|&v| s15_fixed16(v)).collect()
+}
+
+fn xyz_tag(xyz: [f64; 3]) -> Vec<u8> {
+    let mut tag = b"XYZ \0\0\0\0".to_vec();
+    tag.extend_from_slice(&xyz_number(xyz));
+    tag
+}
+
+fn text_tag(text: &str) -> Vec<u8> {
+    let mut tag = b"text\0\0\0\0".to_vec();
+    tag.extend_from_slice(text.as_bytes());
+    tag.push(0);
+    tag
+}
+
+fn desc_tag(description: &str) -> Vec<u8> {
+    let mut tag = b"desc\0\0\0\0".to_vec();
+    tag.extend_from_slice(&(description.len() as u32 + 1).to_be_bytes());
+    tag.extend_from_slice(description.as_bytes());
+    tag.push(0);
+    // Empty unicode and script code descriptions.
+    tag.extend_from_slice(&[0; 8]);
+    tag.extend_from_slice(&[0; 3]);
+    tag.extend_from_slice(&[0; 67]);
+    tag
+}
+
+/// Tone curve of sRGB, sampled on 256 points which is enough for 8 bits tiles.
+fn curve_tag() -> Vec<u8> {
+    let count = 256;
+    let mut tag = b"curv\0\0\0\0".to_vec();
+    tag.extend_from_slice(&(count as u32).to_be_bytes());
+    for i in 0..count {
+        let v = i as f64 / (count - 1) as f64;
+        let linear = if v <= 0.04045 {
+            v / 12.92
+        } else {
+            ((v + 0.055) / 1.055).powf(2.4)
+        };
+        tag.extend_from_slice(&((linear * 65535.0).round() as u16).to_be_bytes());
+    }
+    tag
+}
@@ -0,0 +1,128 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::{InputImage, Tile, TilePixel};
+use anyhow::Context;
+use image::codecs::png::PngDecoder;
+use image::{ColorType, DynamicImage, ImageBuffer, ImageDecoder, ImageFormat};
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read};
+use std::ops::Range;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Whether the input is a png image, whose rows can be decoded one at a time.
+pub fn is_png(input: &InputImage) -> bool {
+    match &input.bytes {
+        Some(bytes) => bytes.starts_with(PNG_SIGNATURE),
+        None => ImageFormat::from_path(&input.path).ok() == Some(ImageFormat::Png),
+    }
+}
+
+/// Rows of a png input image, decoded from top to bottom without keeping the whole image.
+/// Interlaced images cannot be streamed, and are still decoded at once by the png decoder.
+pub struct RowStream<'a> {
+    reader: Box<dyn Read + Send + 'a>,
+    color_type: ColorType,
+    width: usize,
+    /// Index of the next row of the reader.
+    next_row: usize,
+}
+
+impl<'a> RowStream<'a> {
+    pub fn open(input: &'a InputImage) -> anyhow::Result<Self> {
+        let context = || format!("Failed to decode {}", input.path.display());
+        let decoder: (ColorType, u32, Box<dyn Read + Send + 'a>) = match &input.bytes {
+            Some(bytes) => {
+                let decoder = PngDecoder::new(Cursor::new(&bytes[..])).with_context(context)?;
+                let (color_type, (width, _)) = (decoder.color_type(), decoder.dimensions());
+                (color_type, width, Box::new(decoder.into_reader()?))
+            }
+            None => {
+                let file = File::open(&input.path).with_context(context)?;
+                let decoder = PngDecoder::new(BufReader::new(file)).with_context(context)?;
+                let (color_type, (width, _)) = (decoder.color_type(), decoder.dimensions());
+                (color_type, width, Box::new(decoder.into_reader()?))
+            }
+        };
+        let (color_type, width, reader) = decoder;
+        Ok(Self {
+            reader,
+            color_type,
+            width: width as usize,
+            next_row: 0,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Index of the first row that can still be read.
+    pub fn next_row(&self) -> usize {
+        self.next_row
+    }
+
+    /// Decode the given columns of the given rows of the image, skipping the rows before them.
+    /// Rows before [`next_row`](Self::next_row) were already skipped and cannot be read anymore.
+    pub fn read<P: TilePixel>(
+        &mut self,
+        columns: Range<usize>,
+        rows: Range<usize>,
+    ) -> anyhow::Result<Tile<P>> {
+        assert!(
+            rows.start >= self.next_row,
+            "Rows are read from top to bottom"
+        );
+        let pixel_bytes = self.color_type.bytes_per_pixel() as usize;
+        let mut row = vec![0; self.width * pixel_bytes];
+        let band = columns.start * pixel_bytes..columns.end * pixel_bytes;
+        let mut strip = Vec::with_capacity(band.len() * rows.len());
+        while self.next_row < rows.end {
+            self.reader.read_exact(&mut row)?;
+            if self.next_row >= rows.start {
+                strip.extend_from_slice(&row[band.clone()]);
+            }
+            self.next_row += 1;
+        }
+        let (width, height) = (columns.len() as u32, rows.len() as u32);
+        let strip = to_dynamic(self.color_type, width, height, strip)
+            .with_context(|| format!("Unsupported png color type {:?}", self.color_type))?;
+        Ok(P::from_dynamic(strip))
+    }
+}
+
+/// Image of the raw rows of a png decoder, whose 16 bits samples are big endian.
+fn to_dynamic(
+    color_type: ColorType,
+    width: u32,
+    height: u32,
+    bytes: Vec<u8>,
+) -> Option<DynamicImage> {
+    let samples = || -> Vec<u16> {
+        let pairs = bytes.chunks_exact(2);
+        pairs.map(|b| u16::from_be_bytes([b[0], b[1]])).collect()
+    };
+    match color_type {
+        ColorType::L8 => ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageLuma8),
+        ColorType::La8 => {
+            ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageLumaA8)
+        }
+        ColorType::Rgb8 => ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageRgb8),
+        ColorType::Rgba8 => {
+            ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageRgba8)
+        }
+        ColorType::L16 => {
+            ImageBuffer::from_raw(width, height, samples()).map(DynamicImage::ImageLuma16)
+        }
+        ColorType::La16 => {
+            ImageBuffer::from_raw(width, height, samples()).map(DynamicImage::ImageLumaA16)
+        }
+        ColorType::Rgb16 => {
+            ImageBuffer::from_raw(width, height, samples()).map(DynamicImage::ImageRgb16)
+        }
+        ColorType::Rgba16 => {
+            ImageBuffer::from_raw(width, height, samples()).map(DynamicImage::ImageRgba16)
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,59 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use image::RgbImage;
+
+/// Number of pixels removed on each side of an image.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Borders {
+    pub left: usize,
+    pub top: usize,
+    pub right: usize,
+    pub bottom: usize,
+}
+
+/// Detect the uniform border rows and columns of an image.
+///
+/// A border row or column is uniform if all its pixels differ from the corner pixel
+/// of that side by at most `tolerance` on every channel.
+/// At least one row and one column are always kept.
+pub fn detect_borders(img: &RgbImage, tolerance: u8) -> Borders {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let close = |a: &image::Rgb<u8>, b: &image::Rgb<u8>| {
+        a.0.iter()
+            .zip(b.0)
+            .all(|(&a, b)| (a as i16 - b as i16).unsigned_abs() <= tolerance as u16)
+    };
+    let uniform_row = |y: usize, x_range: std::ops::Range<usize>, reference| {
+        x_range
+            .into_iter()
+            .all(|x| close(img.get_pixel(x as u32, y as u32), reference))
+    };
+    let uniform_column = |x: usize, y_range: std::ops::Range<usize>, reference| {
+        y_range
+            .into_iter()
+            .all(|y| close(img.get_pixel(x as u32, y as u32), reference))
+    };
+
+    let mut borders = Borders::default();
+    let top_left = img.get_pixel(0, 0);
+    let bottom_right = img.get_pixel(width as u32 - 1, height as u32 - 1);
+    while borders.top + 1 < height && uniform_row(borders.top, 0..width, top_left) {
+        borders.top += 1;
+    }
+    while borders.top + borders.bottom + 1 < height
+        && uniform_row(height - 1 - borders.bottom, 0..width, bottom_right)
+    {
+        borders.bottom += 1;
+    }
+    // Columns are only checked on the remaining rows.
+    let rows = borders.top..height - borders.bottom;
+    while borders.left + 1 < width && uniform_column(borders.left, rows.clone(), top_left) {
+        borders.left += 1;
+    }
+    while borders.left + borders.right + 1 < width
+        && uniform_column(width - 1 - borders.right, rows.clone(), bottom_right)
+    {
+        borders.right += 1;
+    }
+    borders
+}
@@ -0,0 +1,89 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::{level_size, tile_bounds, tile_grid, DziInfo, TileNameTemplate};
+use image::io::Reader as ImageReader;
+use image::GenericImageView;
+use rayon::prelude::*;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+/// What is wrong with a tile of a tileset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TileProblem {
+    Missing,
+    Corrupt(String),
+    WrongSize {
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+}
+
+impl fmt::Display for TileProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Missing => write!(f, "missing"),
+            Self::Corrupt(err) => write!(f, "corrupt ({})", err),
+            Self::WrongSize { expected, actual } => write!(
+                f,
+                "size {}x{} instead of {}x{}",
+                actual.0, actual.1, expected.0, expected.1
+            ),
+        }
+    }
+}
+
+/// Check that every tile of the given levels expected from the .dzi description
+/// exists in `files_dir`, decodes and has the correct size.
+/// Return the problems found, as (level, tx, ty, problem), ordered by level and coordinates.
+pub fn verify_tiles(
+    dzi: &DziInfo,
+    files_dir: &Path,
+    names: &TileNameTemplate,
+    levels: RangeInclusive<usize>,
+) -> Vec<(usize, usize, usize, TileProblem)> {
+    let mut tiles = Vec::new();
+    for level in levels {
+        let (level_width, level_height) = level_size(dzi.width, dzi.height, level);
+        let (columns, rows) = tile_grid(level_width, level_height, dzi.tile_size);
+        for tx in 0..columns {
+            for ty in 0..rows {
+                let (x, y) = tile_bounds(
+                    level_width,
+                    level_height,
+                    dzi.tile_size,
+                    dzi.overlap,
+                    tx,
+                    ty,
+                );
+                let expected = (x.len() as u32, y.len() as u32);
+                tiles.push((level, tx, ty, expected));
+            }
+        }
+    }
+    tiles
+        .into_par_iter()
+        .filter_map(|(level, tx, ty, expected)| {
+            let name = names.render(level, tx, ty, &dzi.format);
+            let path = files_dir.join(level.to_string()).join(name);
+            check_tile(&path, expected).map(|problem| (level, tx, ty, problem))
+        })
+        .collect()
+}
+
+fn check_tile(path: &Path, expected: (u32, u32)) -> Option<TileProblem> {
+    if !path.is_file() {
+        return Some(TileProblem::Missing);
+    }
+    let decoded = ImageReader::open(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|reader| Ok(reader.with_guessed_format()?.decode()?));
+    match decoded {
+        Err(err) => Some(TileProblem::Corrupt(err.to_string())),
+        Ok(img) if img.dimensions() != expected => Some(TileProblem::WrongSize {
+            expected,
+            actual: img.dimensions(),
+        }),
+        Ok(_) => None,
+    }
+}
@@ -0,0 +1,134 @@
+// SPDX-License-Identifier: MPL-2.0
+
+mod common;
+
+use common::{open_image, solid_image, zoomtiler, zoomtiler_ok};
+use image::GenericImageView;
+use std::path::Path;
+use zoomtiler::DziInfo;
+
+/// Batch root with panoramas `a` of two images, `b` with a corrupt image, and `c`.
+fn batch_root(root: &Path) {
+    for name in ["a", "b", "c"] {
+        std::fs::create_dir_all(root.join(name)).unwrap();
+    }
+    solid_image(&root.join("a"), "2.png", 100, 50, [0, 0, 255]);
+    solid_image(&root.join("a"), "1.png", 200, 50, [255, 0, 0]);
+    std::fs::write(root.join("a/notes.txt"), "not an image").unwrap();
+    std::fs::write(root.join("b/1.png"), "not a png").unwrap();
+    solid_image(&root.join("c"), "1.png", 80, 40, [0, 255, 0]);
+}
+
+#[test]
+fn batch_tiles_each_subdirectory_past_failures() {
+    let dir = tempfile::tempdir().unwrap();
+    batch_root(&dir.path().join("scans"));
+    let output = zoomtiler([
+        "--batch".as_ref(),
+        dir.path().join("scans").as_os_str(),
+        "--output".as_ref(),
+        dir.path().join("out/tiles.dzi").as_os_str(),
+        "--format".as_ref(),
+        "png".as_ref(),
+    ]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("1 of 3 panoramas failed"), "{}", stderr);
+
+    let a = open_image(&dir.path().join("out/a/tiles_files/9/0_0.png"));
+    assert_eq!(a.dimensions(), (300, 50));
+    assert_eq!(a.get_pixel(0, 0).0, [255, 0, 0, 255]);
+    assert_eq!(a.get_pixel(250, 0).0, [0, 0, 255, 255]);
+    assert!(!dir.path().join("out/b/tiles.dzi").exists());
+    assert!(dir.path().join("out/c/tiles.dzi").is_file());
+}
+
+#[test]
+fn batch_stops_at_the_first_failure_with_fail_fast() {
+    let dir = tempfile::tempdir().unwrap();
+    batch_root(&dir.path().join("scans"));
+    let output = zoomtiler([
+        "--batch".as_ref(),
+        dir.path().join("scans").as_os_str(),
+        "--output".as_ref(),
+        dir.path().join("out/tiles.dzi").as_os_str(),
+        "--fail-fast".as_ref(),
+    ]);
+    assert!(!output.status.success());
+    assert!(dir.path().join("out/a/tiles.dzi").is_file());
+    assert!(!dir.path().join("out/c").exists());
+}
+
+#[test]
+fn batch_configures_the_threads_once_for_all_panoramas() {
+    let dir = tempfile::tempdir().unwrap();
+    let scans = dir.path().join("scans");
+    batch_root(&scans);
+    std::fs::remove_dir_all(scans.join("b")).unwrap();
+    zoomtiler_ok([
+        "--batch".as_ref(),
+        scans.as_os_str(),
+        "--output".as_ref(),
+        dir.path().join("out/tiles.dzi").as_os_str(),
+        "--threads".as_ref(),
+        "2".as_ref(),
+    ]);
+    assert!(dir.path().join("out/a/tiles.dzi").is_file());
+    assert!(dir.path().join("out/c/tiles.dzi").is_file());
+}
+
+#[test]
+fn collection_references_the_dzi_of_each_panorama() {
+    let dir = tempfile::tempdir().unwrap();
+    let a1 = solid_image(dir.path(), "a1.png", 300, 200, [255, 0, 0]);
+    let a2 = solid_image(dir.path(), "a2.png", 200, 200, [0, 255, 0]);
+    let b1 = solid_image(dir.path(), "b1.png", 50, 60, [0, 0, 255]);
+    let out = dir.path().join("out");
+    zoomtiler_ok([
+        "collection".as_ref(),
+        "--output".as_ref(),
+        out.join("panoramas.dzc").as_os_str(),
+        "--format".as_ref(),
+        "png".as_ref(),
+        "--tilesize".as_ref(),
+        "256".as_ref(),
+        "--panorama".as_ref(),
+        a1.as_os_str(),
+        a2.as_os_str(),
+        "--panorama".as_ref(),
+        b1.as_os_str(),
+    ]);
+    let dzc = std::fs::read_to_string(out.join("panoramas.dzc")).unwrap();
+    assert!(
+        dzc.contains(r#"TileSize="256" Format="png" NextItemId="2""#),
+        "{}",
+        dzc
+    );
+    let mut sizes = Vec::new();
+    for id in 0..2 {
+        let xml = std::fs::read_to_string(out.join(format!("{}.dzi", id))).unwrap();
+        let dzi = DziInfo::parse(&xml).unwrap();
+        let item = format!(
+            r#"<I Id="{id}" N="{id}" Source="{id}.dzi"><Size Width="{}" Height="{}"/></I>"#,
+            dzi.width,
+            dzi.height,
+            id = id
+        );
+        assert!(dzc.contains(&item), "{}", dzc);
+        assert!(out.join(format!("{}_files/0/0_0.png", id)).is_file());
+        sizes.push((dzi.width, dzi.height));
+    }
+    assert_eq!(sizes, [(500, 200), (50, 60)]);
+}
+
+#[test]
+fn batch_rejects_explicit_inputs() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = solid_image(dir.path(), "1.png", 10, 10, [0, 0, 0]);
+    let output = zoomtiler([
+        input.as_os_str(),
+        "--batch".as_ref(),
+        dir.path().as_os_str(),
+    ]);
+    assert!(!output.status.success());
+}
@@ -0,0 +1,62 @@
+// SPDX-License-Identifier: MPL-2.0
+
+mod common;
+
+use common::{solid_image, zoomtiler, zoomtiler_ok};
+use zoomtiler::{sha256_hex, Checksums};
+
+#[test]
+fn sha256_of_known_messages() {
+    assert_eq!(
+        sha256_hex(b""),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+    assert_eq!(
+        sha256_hex(b"abc"),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    // Two blocks once padded.
+    assert_eq!(
+        sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+        "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+    );
+}
+
+#[test]
+fn checksums_of_all_tiles_are_verified() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = solid_image(dir.path(), "input.png", 600, 300, [10, 20, 30]);
+    let dzi = dir.path().join("out/tiles.dzi");
+    zoomtiler_ok([
+        input.as_os_str(),
+        "--output".as_ref(),
+        dzi.as_os_str(),
+        "--format".as_ref(),
+        "png".as_ref(),
+        "--tilesize".as_ref(),
+        "256".as_ref(),
+        "--checksums".as_ref(),
+    ]);
+    let out = dir.path().join("out");
+    let checksums = Checksums::load(&out.join("checksums.txt")).unwrap();
+    // Levels 0 to 8 have a single tile, level 9 has 2x1 tiles and level 10 has 3x2 tiles.
+    assert_eq!(checksums.entries.len(), 9 + 2 + 6);
+    let bytes = std::fs::read(out.join("tiles_files/10/2_1.png")).unwrap();
+    assert_eq!(
+        checksums.entries["tiles_files/10/2_1.png"],
+        sha256_hex(&bytes)
+    );
+    zoomtiler_ok(["verify".as_ref(), dzi.as_os_str(), "--checksums".as_ref()]);
+
+    // A valid tile of the correct size, but different from the generated one.
+    solid_image(&out.join("tiles_files/10"), "0_0.png", 256, 256, [0, 0, 0]);
+    zoomtiler_ok(["verify".as_ref(), dzi.as_os_str()]);
+    let output = zoomtiler(["verify".as_ref(), dzi.as_os_str(), "--checksums".as_ref()]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("tiles_files/10/0_0.png: checksum mismatch"),
+        "{}",
+        stderr
+    );
+}
@@ -0,0 +1,93 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Helpers shared by the integration tests.
+
+#![allow(dead_code)]
+
+use image::io::Reader as ImageReader;
+use image::{DynamicImage, Rgb, RgbImage};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Save a solid color image and return its path.
+pub fn solid_image(dir: &Path, name: &str, width: u32, height: u32, color: [u8; 3]) -> PathBuf {
+    let path = dir.join(name);
+    RgbImage::from_pixel(width, height, Rgb(color))
+        .save(&path)
+        .unwrap();
+    path
+}
+
+/// Run zoomtiler with the given arguments.
+pub fn zoomtiler<I, S>(args: I) -> Output
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<std::ffi::OsStr>,
+{
+    Command::new(env!("CARGO_BIN_EXE_zoomtiler"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Run zoomtiler and check that it succeeded.
+pub fn zoomtiler_ok<I, S>(args: I) -> Output
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<std::ffi::OsStr>,
+{
+    let output = zoomtiler(args);
+    assert!(
+        output.status.success(),
+        "zoomtiler failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+/// Sorted names of the entries of a directory.
+pub fn entries(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    names
+}
+
+pub fn open_image(path: &Path) -> DynamicImage {
+    ImageReader::open(path).unwrap().decode().unwrap()
+}
+
+/// Number of tiles in each dimension at every level, from level 0 to the full resolution.
+pub fn expected_grids(width: usize, height: usize, tile_size: usize) -> Vec<(usize, usize)> {
+    let mut grids = Vec::new();
+    let (mut w, mut h) = (width, height);
+    loop {
+        grids.push((w.div_ceil(tile_size), h.div_ceil(tile_size)));
+        if w == 1 && h == 1 {
+            break;
+        }
+        w = w.div_ceil(2);
+        h = h.div_ceil(2);
+    }
+    grids.reverse();
+    grids
+}
+
+/// Check that every level contains exactly the expected grid of tiles.
+pub fn assert_tile_grids(files_dir: &Path, extension: &str, grids: &[(usize, usize)]) {
+    assert_eq!(entries(files_dir).len(), grids.len());
+    for (level, (cols, rows)) in grids.iter().enumerate() {
+        let mut expected: Vec<String> = (0..*cols)
+            .flat_map(|tx| (0..*rows).map(move |ty| format!("{}_{}.{}", tx, ty, extension)))
+            .collect();
+        expected.sort();
+        assert_eq!(
+            entries(&files_dir.join(level.to_string())),
+            expected,
+            "level {}",
+            level
+        );
+    }
+}
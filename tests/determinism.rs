@@ -0,0 +1,86 @@
+// SPDX-License-Identifier: MPL-2.0
+
+mod common;
+
+use common::{entries, solid_image, zoomtiler_ok};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Tile the same slices into the given output, with jpg tiles and all cores unless limited.
+fn tile(inputs: &[PathBuf], dzi: &Path, extra_args: &[&str]) {
+    let mut args: Vec<OsString> = inputs.iter().map(|p| p.clone().into()).collect();
+    args.extend(["--output".into(), dzi.into()]);
+    args.extend(["--tilesize", "128", "--feather", "16"].map(Into::into));
+    args.extend(extra_args.iter().map(Into::into));
+    zoomtiler_ok(args);
+}
+
+/// Relative paths of all the files in a directory, recursively.
+fn files(dir: &Path, prefix: &Path) -> Vec<PathBuf> {
+    let mut all = Vec::new();
+    for name in entries(dir) {
+        let path = dir.join(&name);
+        if path.is_dir() {
+            all.extend(files(&path, &prefix.join(&name)));
+        } else {
+            all.push(prefix.join(&name));
+        }
+    }
+    all
+}
+
+/// Check that both directories have the same files with the same bytes.
+fn assert_identical(first: &Path, second: &Path) {
+    let first_files = files(first, Path::new(""));
+    assert_eq!(first_files, files(second, Path::new("")));
+    assert!(first_files.len() > 20);
+    for file in first_files {
+        let first_bytes = std::fs::read(first.join(&file)).unwrap();
+        let second_bytes = std::fs::read(second.join(&file)).unwrap();
+        assert!(first_bytes == second_bytes, "{} differs", file.display());
+    }
+}
+
+/// Save an image whose pixels all differ from their neighbors, so that tiles mixed up
+/// between threads or positions show up, unlike with solid colors.
+fn noisy_image(dir: &Path, name: &str, width: u32, height: u32, seed: u32) -> PathBuf {
+    let path = dir.join(name);
+    image::RgbImage::from_fn(width, height, |x, y| {
+        let noise =
+            (x.wrapping_mul(7919) ^ y.wrapping_mul(104_729) ^ seed).wrapping_mul(2_654_435_761);
+        image::Rgb([(noise >> 24) as u8, (noise >> 16) as u8, (noise >> 8) as u8])
+    })
+    .save(&path)
+    .unwrap();
+    path
+}
+
+#[test]
+fn two_runs_produce_identical_bytes() {
+    let dir = tempfile::tempdir().unwrap();
+    let inputs = [
+        solid_image(dir.path(), "1.png", 300, 200, [200, 10, 10]),
+        solid_image(dir.path(), "2.png", 500, 200, [10, 200, 10]),
+        solid_image(dir.path(), "3.png", 250, 200, [10, 10, 200]),
+    ];
+    let first = dir.path().join("first");
+    let second = dir.path().join("second");
+    tile(&inputs, &first.join("tiles.dzi"), &[]);
+    tile(&inputs, &second.join("tiles.dzi"), &[]);
+    assert_identical(&first, &second);
+}
+
+#[test]
+fn thread_count_does_not_change_the_bytes() {
+    let dir = tempfile::tempdir().unwrap();
+    let inputs = [
+        noisy_image(dir.path(), "1.png", 300, 300, 1),
+        noisy_image(dir.path(), "2.png", 500, 300, 2),
+        noisy_image(dir.path(), "3.png", 250, 300, 3),
+    ];
+    let serial = dir.path().join("serial");
+    let parallel = dir.path().join("parallel");
+    tile(&inputs, &serial.join("tiles.dzi"), &["--threads", "1"]);
+    tile(&inputs, &parallel.join("tiles.dzi"), &["--threads", "4"]);
+    assert_identical(&serial, &parallel);
+}
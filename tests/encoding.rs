@@ -0,0 +1,165 @@
+// SPDX-License-Identifier: MPL-2.0
+
+mod common;
+
+use common::{open_image, solid_image, zoomtiler_ok};
+use std::path::{Path, PathBuf};
+
+/// Tile a single image and return the bytes of its first full resolution tile.
+fn first_tile(dir: &Path, format: &str, extra_args: &[&str]) -> Vec<u8> {
+    let input = solid_image(dir, "input.png", 300, 200, [200, 10, 10]);
+    let dzi = dir.join(format!("{}/tiles.dzi", format));
+    let mut args: Vec<PathBuf> = vec![input, "--output".into(), dzi, "--format".into()];
+    args.push(format.into());
+    args.extend(extra_args.iter().map(Into::into));
+    zoomtiler_ok(args);
+    let tile = dir.join(format!("{}/tiles_files/9/0_0.{}", format, format));
+    // Tags must not prevent decoding the tiles.
+    open_image(&tile);
+    std::fs::read(tile).unwrap()
+}
+
+fn contains(bytes: &[u8], pattern: &[u8]) -> bool {
+    bytes.windows(pattern.len()).any(|w| w == pattern)
+}
+
+/// Content of the first segment of a JPEG with the given marker, after its length.
+/// Segments are walked from the start, so that bytes of other segments are not mistaken for it.
+fn jpeg_segment(bytes: &[u8], marker: u8) -> &[u8] {
+    assert_eq!(&bytes[..2], b"\xff\xd8", "not a JPEG");
+    let mut i = 2;
+    while bytes[i] == 0xff && bytes[i + 1] != 0xda {
+        let length = u16::from_be_bytes([bytes[i + 2], bytes[i + 3]]) as usize;
+        if bytes[i + 1] == marker {
+            return &bytes[i + 4..i + 2 + length];
+        }
+        i += 2 + length;
+    }
+    panic!("No segment with marker {:02x}", marker)
+}
+
+#[test]
+fn jpg_tiles_embed_an_srgb_profile() {
+    let dir = tempfile::tempdir().unwrap();
+    let tile = first_tile(dir.path(), "jpg", &[]);
+    assert!(contains(&tile, b"ICC_PROFILE\0"));
+    assert!(contains(&tile, b"sRGB"));
+}
+
+#[test]
+fn png_tiles_have_an_srgb_chunk() {
+    let dir = tempfile::tempdir().unwrap();
+    let tile = first_tile(dir.path(), "png", &[]);
+    // The chunk comes right after the IHDR one, before the image data.
+    assert_eq!(&tile[33..41], b"\0\0\0\x01sRGB");
+}
+
+#[test]
+fn srgb_tags_can_be_disabled() {
+    let dir = tempfile::tempdir().unwrap();
+    let jpg = first_tile(dir.path(), "jpg", &["--no-srgb-tag"]);
+    assert!(!contains(&jpg, b"ICC_PROFILE"));
+    let png = first_tile(dir.path(), "png", &["--no-srgb-tag"]);
+    assert!(!contains(&png, b"sRGB"));
+}
+
+#[test]
+fn jpg_tiles_are_baseline_unless_progressive() {
+    let dir = tempfile::tempdir().unwrap();
+    // Start of frame markers of baseline and progressive JPEGs.
+    let (baseline, progressive) = (b"\xff\xc0", b"\xff\xc2");
+    let tile = first_tile(dir.path(), "jpg", &[]);
+    assert!(contains(&tile, baseline) && !contains(&tile, progressive));
+    let dir = tempfile::tempdir().unwrap();
+    let tile = first_tile(dir.path(), "jpg", &["--progressive"]);
+    assert!(contains(&tile, progressive) && !contains(&tile, baseline));
+}
+
+#[test]
+fn jpeg_subsampling_sets_the_sampling_factors() {
+    // Horizontal and vertical sampling factors of the Y, Cb and Cr components.
+    let sampling_factors = |subsampling: &str| {
+        let dir = tempfile::tempdir().unwrap();
+        let tile = first_tile(dir.path(), "jpg", &["--jpeg-subsampling", subsampling]);
+        // Precision, height, width and number of components,
+        // then the identifier, sampling factors and quantization table of each component.
+        let frame = jpeg_segment(&tile, 0xc0).to_vec();
+        assert_eq!(frame[5], 3);
+        [frame[7], frame[10], frame[13]]
+    };
+    assert_eq!(sampling_factors("4:4:4"), [0x11, 0x11, 0x11]);
+    assert_eq!(sampling_factors("4:2:2"), [0x21, 0x11, 0x11]);
+    assert_eq!(sampling_factors("4:2:0"), [0x22, 0x11, 0x11]);
+}
+
+#[test]
+fn quality_scales_the_quantization_tables() {
+    // Sum of the values of the first quantization table, after its precision and identifier.
+    let quantization = |quality: &str| -> u32 {
+        let dir = tempfile::tempdir().unwrap();
+        let tile = first_tile(dir.path(), "jpg", &["--quality", quality]);
+        let table = jpeg_segment(&tile, 0xdb);
+        assert_eq!(table[0], 0, "8 bits table 0");
+        table[1..65].iter().map(|&q| q as u32).sum()
+    };
+    assert_eq!(quantization("100"), 64);
+    assert!(quantization("30") > quantization("90"));
+}
+
+/// Tile a noisy 512x512 image, expensive to encode, into a single jpg tile of quality 95.
+fn noisy_tile(dir: &Path, max_tile_bytes: &str) -> (Vec<u8>, String) {
+    let input = dir.join("noise.png");
+    image::RgbImage::from_fn(512, 512, |x, y| {
+        let noise = (x.wrapping_mul(7919) ^ y.wrapping_mul(104_729)).wrapping_mul(2_654_435_761);
+        image::Rgb([(noise >> 24) as u8, (noise >> 16) as u8, (noise >> 8) as u8])
+    })
+    .save(&input)
+    .unwrap();
+    let dzi = dir.join("out/tiles.dzi");
+    let output = zoomtiler_ok([
+        input.as_os_str(),
+        "--output".as_ref(),
+        dzi.as_os_str(),
+        "--quality".as_ref(),
+        "95".as_ref(),
+        "--max-tile-bytes".as_ref(),
+        max_tile_bytes.as_ref(),
+    ]);
+    let tile = std::fs::read(dir.join("out/tiles_files/9/0_0.jpg")).unwrap();
+    (tile, String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+#[test]
+fn max_tile_bytes_lowers_the_quality_of_large_tiles() {
+    let dir = tempfile::tempdir().unwrap();
+    let (tile, stderr) = noisy_tile(dir.path(), "100000");
+    assert!(tile.len() <= 100_000, "{} bytes", tile.len());
+    assert!(!stderr.contains("over the budget"), "{}", stderr);
+}
+
+#[test]
+fn tiles_over_budget_are_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let (_, stderr) = noisy_tile(dir.path(), "1000");
+    assert!(stderr.contains("tiles_files/9/0_0.jpg has"), "{}", stderr);
+    assert!(
+        stderr.contains("over the --max-tile-bytes budget"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn grayscale_tiles_have_a_single_channel() {
+    let dir = tempfile::tempdir().unwrap();
+    for format in ["jpg", "png"] {
+        first_tile(dir.path(), format, &["--grayscale"]);
+        for tile in ["9/0_0", "0/0_0"] {
+            let path = dir
+                .path()
+                .join(format!("{}/tiles_files/{}.{}", format, tile, format));
+            let img = open_image(&path);
+            assert!(img.as_luma8().is_some(), "{} is {:?}", tile, img.color());
+        }
+    }
+}
@@ -0,0 +1,98 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use std::path::PathBuf;
+use zoomtiler::{
+    base_level, input_columns, level_size, levels_for, tile_bounds, tile_grid, tile_sources,
+    Borders, ImageSpan, InputImage, Rotation, TilingConfig,
+};
+
+fn input(width: usize, height: usize) -> InputImage {
+    InputImage {
+        path: PathBuf::from("input.png"),
+        width,
+        height,
+        offset_y: 0,
+        pad_left: 0,
+        bytes: None,
+        rotation: Rotation::None,
+        resize: None,
+        trim: Borders::default(),
+    }
+}
+
+fn config(tile_size: usize, gap: usize) -> TilingConfig {
+    TilingConfig {
+        tile_size,
+        gap,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn levels_double_the_resolution() {
+    assert_eq!(levels_for(1), 1);
+    assert_eq!(levels_for(2), 2);
+    assert_eq!(levels_for(3), 3);
+    assert_eq!(levels_for(1024), 11);
+    assert_eq!(levels_for(1025), 12);
+    assert_eq!(base_level(1000, 300), 10);
+    assert_eq!(level_size(1000, 300, 10), (1000, 300));
+    assert_eq!(level_size(1000, 300, 9), (500, 150));
+    assert_eq!(level_size(1000, 300, 1), (2, 1));
+    assert_eq!(level_size(1000, 300, 0), (1, 1));
+}
+
+#[test]
+fn edge_tiles_are_smaller() {
+    assert_eq!(tile_grid(1000, 300, 256), (4, 2));
+    assert_eq!(tile_bounds(1000, 300, 256, 0, 0, 0), (0..256, 0..256));
+    assert_eq!(tile_bounds(1000, 300, 256, 0, 3, 1), (768..1000, 256..300));
+    // Tiles overlap their neighbors, except past the edges of the level.
+    assert_eq!(tile_bounds(1000, 300, 256, 1, 1, 0), (255..513, 0..257));
+}
+
+#[test]
+fn tiles_map_to_the_pixels_of_their_inputs() {
+    let mut second = input(200, 80);
+    second.offset_y = 20;
+    second.pad_left = 10;
+    second.trim.left = 5;
+    second.trim.top = 3;
+    let inputs = [input(300, 100), second, input(100, 100)];
+    let config = config(256, 4);
+    assert_eq!(input_columns(&inputs, 4), [0..300, 304..504, 508..608]);
+
+    // Base level of 608x100 pixels, the second tile covers columns 256..512.
+    assert_eq!(
+        tile_sources(&inputs, &config, 10, 1, 0),
+        [
+            ImageSpan {
+                input: 0,
+                columns: 256..300,
+                rows: 0..100,
+                image_columns: 256..300,
+                image_rows: 0..100,
+            },
+            ImageSpan {
+                input: 1,
+                columns: 314..504,
+                rows: 20..100,
+                image_columns: 5..195,
+                image_rows: 3..83,
+            },
+            ImageSpan {
+                input: 2,
+                columns: 508..512,
+                rows: 0..100,
+                image_columns: 0..4,
+                image_rows: 0..100,
+            },
+        ]
+    );
+
+    // The single tile of level 8, of 152x25 pixels, covers the whole panorama.
+    let spans = tile_sources(&inputs, &config, 8, 0, 0);
+    let ids: Vec<usize> = spans.iter().map(|span| span.input).collect();
+    assert_eq!(ids, [0, 1, 2]);
+    assert_eq!(spans[2].columns, 508..608);
+}
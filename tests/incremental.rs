@@ -0,0 +1,311 @@
+// SPDX-License-Identifier: MPL-2.0
+
+mod common;
+
+use common::{open_image, solid_image, zoomtiler_ok};
+use image::GenericImageView;
+use std::path::Path;
+use std::process::Command;
+use zoomtiler::{BuildInfo, Overview};
+
+const RED: [u8; 3] = [255, 0, 0];
+const GREEN: [u8; 3] = [0, 255, 0];
+const BLUE: [u8; 3] = [0, 0, 255];
+
+/// Tile the 300, 500 and 250 wide slices of the directory into png tiles of 256.
+fn tile(dir: &Path, extra_args: &[&str]) {
+    let mut args = vec![
+        dir.join("1.png").into_os_string(),
+        dir.join("2.png").into_os_string(),
+        dir.join("3.png").into_os_string(),
+        "--output".into(),
+        dir.join("out/tiles.dzi").into_os_string(),
+    ];
+    args.extend(["--format", "png", "--tilesize", "256"].map(Into::into));
+    args.extend(extra_args.iter().map(Into::into));
+    zoomtiler_ok(args);
+}
+
+fn pixel(dir: &Path, level: usize, tile: &str) -> [u8; 3] {
+    let path = dir
+        .join("out/tiles_files")
+        .join(level.to_string())
+        .join(tile);
+    open_image(&path).to_rgb8().get_pixel(0, 0).0
+}
+
+/// Replace a tile by a solid white one of the same size, to detect if it is regenerated.
+fn mark_tile(dir: &Path, level: usize, tile: &str) {
+    let level_dir = dir.join("out/tiles_files").join(level.to_string());
+    let (width, height) = open_image(&level_dir.join(tile)).dimensions();
+    solid_image(&level_dir, tile, width, height, [255, 255, 255]);
+}
+
+fn setup() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    solid_image(dir.path(), "1.png", 300, 200, RED);
+    solid_image(dir.path(), "2.png", 500, 200, GREEN);
+    solid_image(dir.path(), "3.png", 250, 200, BLUE);
+    tile(dir.path(), &[]);
+    dir
+}
+
+#[test]
+fn only_tiles_of_changed_inputs_are_regenerated() {
+    let dir = setup();
+    mark_tile(dir.path(), 11, "0_0.png");
+    mark_tile(dir.path(), 11, "4_0.png");
+    mark_tile(dir.path(), 10, "0_0.png");
+
+    // The last slice covers the columns 800 to 1050, in tiles 3 and 4.
+    solid_image(dir.path(), "3.png", 250, 200, RED);
+    tile(dir.path(), &[]);
+    assert_eq!(pixel(dir.path(), 11, "0_0.png"), [255, 255, 255]);
+    assert_eq!(pixel(dir.path(), 11, "4_0.png"), RED);
+    // Only the right pyramid ancestors are regenerated.
+    assert_eq!(pixel(dir.path(), 10, "2_0.png"), RED);
+    assert_eq!(pixel(dir.path(), 10, "0_0.png"), [255, 255, 255]);
+}
+
+#[test]
+fn unchanged_inputs_regenerate_nothing() {
+    let dir = setup();
+    mark_tile(dir.path(), 11, "2_0.png");
+    tile(dir.path(), &[]);
+    assert_eq!(pixel(dir.path(), 11, "2_0.png"), [255, 255, 255]);
+    tile(dir.path(), &["--quiet"]);
+    assert_eq!(pixel(dir.path(), 11, "2_0.png"), [255, 255, 255]);
+}
+
+#[test]
+fn changed_settings_regenerate_everything() {
+    let dir = setup();
+    mark_tile(dir.path(), 11, "2_0.png");
+    tile(dir.path(), &["--background", "101010"]);
+    assert_eq!(pixel(dir.path(), 11, "2_0.png"), GREEN);
+}
+
+#[test]
+fn changed_level_count_regenerates_everything() {
+    let dir = tempfile::tempdir().unwrap();
+    let dzi = dir.path().join("out/tiles.dzi");
+    let tile = || {
+        let inputs = [dir.path().join("1.png"), dir.path().join("2.png")];
+        let mut args: Vec<_> = inputs.iter().map(|p| p.as_os_str().to_owned()).collect();
+        args.extend(["--output".into(), dzi.clone().into_os_string()]);
+        args.extend(["--format", "png", "--tilesize", "128"].map(Into::into));
+        zoomtiler_ok(args);
+    };
+    solid_image(dir.path(), "1.png", 300, 200, RED);
+    solid_image(dir.path(), "2.png", 100, 200, GREEN);
+    tile();
+    // The panorama grows from 400 to 600 pixels wide, from 10 levels to 11.
+    solid_image(dir.path(), "2.png", 300, 200, GREEN);
+    tile();
+    assert_eq!(pixel(dir.path(), 10, "0_0.png"), RED);
+    zoomtiler_ok(["verify".as_ref(), dzi.as_os_str()]);
+}
+
+#[test]
+fn feathered_seams_of_changed_inputs_are_regenerated() {
+    let dir = tempfile::tempdir().unwrap();
+    let tile = |output: &str, extra_args: &[&str]| {
+        let inputs = [dir.path().join("1.png"), dir.path().join("2.png")];
+        let mut args: Vec<_> = inputs.iter().map(|p| p.as_os_str().to_owned()).collect();
+        args.extend(["--output".into(), dir.path().join(output).into_os_string()]);
+        args.extend(["--format", "png", "--tilesize", "256", "--feather", "32"].map(Into::into));
+        args.extend(extra_args.iter().map(Into::into));
+        zoomtiler_ok(args);
+    };
+    solid_image(dir.path(), "1.png", 256, 200, RED);
+    solid_image(dir.path(), "2.png", 256, 200, GREEN);
+    tile("out/tiles.dzi", &[]);
+    // The first tile ends with half of the band blended with the second image.
+    solid_image(dir.path(), "2.png", 256, 200, BLUE);
+    tile("out/tiles.dzi", &[]);
+    tile("full/tiles.dzi", &["--force"]);
+    let tile_image = |output: &str| open_image(&dir.path().join(output).join("9/0_0.png"));
+    assert_eq!(
+        tile_image("out/tiles_files").to_rgb8(),
+        tile_image("full/tiles_files").to_rgb8()
+    );
+}
+
+#[test]
+fn force_regenerates_everything() {
+    let dir = setup();
+    mark_tile(dir.path(), 11, "2_0.png");
+    tile(dir.path(), &["--force"]);
+    assert_eq!(pixel(dir.path(), 11, "2_0.png"), GREEN);
+}
+
+#[test]
+fn only_rows_regenerates_the_given_rows() {
+    let dir = tempfile::tempdir().unwrap();
+    solid_image(dir.path(), "1.png", 300, 600, RED);
+    solid_image(dir.path(), "2.png", 500, 600, GREEN);
+    solid_image(dir.path(), "3.png", 250, 600, BLUE);
+    tile(dir.path(), &[]);
+    mark_tile(dir.path(), 10, "0_0.png");
+    mark_tile(dir.path(), 10, "0_1.png");
+
+    solid_image(dir.path(), "1.png", 300, 600, BLUE);
+    tile(dir.path(), &["--only-rows", "1..2"]);
+    assert_eq!(pixel(dir.path(), 11, "0_0.png"), RED);
+    assert_eq!(pixel(dir.path(), 11, "0_1.png"), BLUE);
+    assert_eq!(pixel(dir.path(), 11, "0_2.png"), RED);
+    // Only the ancestors of the given rows are regenerated.
+    assert_eq!(pixel(dir.path(), 10, "0_0.png"), RED);
+    assert_eq!(pixel(dir.path(), 10, "0_1.png"), [255, 255, 255]);
+
+    // The cache of the previous build still detects the changed input.
+    tile(dir.path(), &[]);
+    assert_eq!(pixel(dir.path(), 11, "0_0.png"), BLUE);
+}
+
+#[test]
+fn build_info_records_the_settings() {
+    let dir = setup();
+    let content = std::fs::read_to_string(dir.path().join("out/tiles.build.json")).unwrap();
+    let info = BuildInfo::parse(&content).unwrap();
+    assert_eq!(info.tile_size, 256);
+    assert_eq!(info.overlap, 0);
+    assert_eq!(info.format, "png");
+    let names: Vec<_> = info
+        .inputs
+        .iter()
+        .map(|p| Path::new(p).file_name())
+        .collect();
+    assert_eq!(
+        names,
+        ["1.png", "2.png", "3.png"].map(|name| Some(name.as_ref()))
+    );
+}
+
+#[test]
+fn rebuild_defaults_to_the_previous_settings() {
+    let dir = setup();
+    mark_tile(dir.path(), 11, "2_0.png");
+    std::fs::remove_file(dir.path().join("out/tiles.cache")).unwrap();
+    // No inputs nor settings: everything comes from tiles.build.json.
+    let dzi = dir.path().join("out/tiles.dzi");
+    zoomtiler_ok(["--output".as_ref(), dzi.as_os_str()]);
+    assert_eq!(pixel(dir.path(), 11, "2_0.png"), GREEN);
+    let xml = std::fs::read_to_string(dzi).unwrap();
+    assert!(
+        xml.contains(r#"TileSize="256" Overlap="0" Format="png""#),
+        "{}",
+        xml
+    );
+}
+
+#[test]
+fn rebuild_reuses_all_the_settings_from_elsewhere() {
+    let dir = tempfile::tempdir().unwrap();
+    let project = dir.path().join("project");
+    std::fs::create_dir(&project).unwrap();
+    solid_image(&project, "1.png", 100, 50, RED);
+    solid_image(&project, "2.png", 100, 50, GREEN);
+    let zoomtiler_in = |cwd: &Path, args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_zoomtiler"))
+            .current_dir(cwd)
+            .args(args)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{}", stderr);
+    };
+    zoomtiler_in(
+        dir.path(),
+        &[
+            "project/2.png",
+            "project/1.png",
+            "--output",
+            "project/out/tiles.dzi",
+            "--sort",
+            "natural",
+            "--offsets",
+            "10,0",
+            "--grayscale",
+            "--format",
+            "png",
+            "--tile-name-template",
+            "tile-{x}-{y}.{ext}",
+        ],
+    );
+    let content = std::fs::read_to_string(project.join("out/tiles.build.json")).unwrap();
+    let info = BuildInfo::parse(&content).unwrap();
+    assert_eq!(info.inputs, ["../1.png", "../2.png"]);
+    assert_eq!(info.settings["offsets"], "0,10");
+
+    // Someone else rebuilds the moved project from its output directory.
+    let moved = dir.path().join("moved");
+    std::fs::rename(&project, &moved).unwrap();
+    std::fs::remove_dir_all(moved.join("out/tiles_files")).unwrap();
+    zoomtiler_in(&moved.join("out"), &["--output", "tiles.dzi"]);
+    let tile = open_image(&moved.join("out/tiles_files/8/tile-0-0.png"));
+    assert!(!moved.join("out/tiles_files/8/0_0.png").exists());
+    let tile = tile.as_luma8().expect("grayscale tile");
+    // The green image is shifted down, below the black background.
+    assert_ne!(tile.get_pixel(50, 0).0, [0]);
+    assert_eq!(tile.get_pixel(150, 0).0, [0]);
+    assert_ne!(tile.get_pixel(150, 20).0, [0]);
+}
+
+#[test]
+fn new_inputs_do_not_inherit_the_recorded_settings() {
+    let dir = tempfile::tempdir().unwrap();
+    let dzi = dir.path().join("out/tiles.dzi");
+    let tile = |input: &Path, extra_args: &[&str]| {
+        let mut args = vec![
+            input.as_os_str().to_owned(),
+            "--output".into(),
+            dzi.clone().into_os_string(),
+        ];
+        args.extend(["--format", "png"].map(Into::into));
+        args.extend(extra_args.iter().map(Into::into));
+        zoomtiler_ok(args);
+    };
+    let info = || {
+        let content = std::fs::read_to_string(dir.path().join("out/tiles.build.json")).unwrap();
+        BuildInfo::parse(&content).unwrap()
+    };
+    let a = solid_image(dir.path(), "a.png", 300, 200, RED);
+    tile(&a, &["--debug-grid"]);
+    let b = solid_image(dir.path(), "b.png", 300, 200, BLUE);
+    tile(&b, &["--skip-color", "0000ff"]);
+    assert!(!info().settings.contains_key("debug-grid"));
+    assert!(!info().skipped_tiles.is_empty());
+    let c = solid_image(dir.path(), "c.png", 300, 200, BLUE);
+    tile(&c, &[]);
+    assert!(!info().settings.contains_key("skip-color"));
+    assert!(info().skipped_tiles.is_empty());
+    // The blue tile is written, without the grid lines.
+    assert_eq!(pixel(dir.path(), 9, "0_0.png"), BLUE);
+    zoomtiler_ok(["verify".as_ref(), dzi.as_os_str()]);
+}
+
+#[test]
+fn build_info_round_trips_unusual_paths() {
+    let dir = tempfile::tempdir().unwrap();
+    let info = BuildInfo {
+        tile_size: 512,
+        overlap: 0,
+        format: "jpg".to_string(),
+        quality: 80,
+        inputs: vec![r#"slices\"1".png"#.to_string(), "é\t2.png".to_string()],
+        settings: [("grayscale", "true"), ("trim-edges", "1,0;0,1")]
+            .map(|(flag, value)| (flag.to_string(), value.to_string()))
+            .into(),
+        levels: Some(3..=10),
+        overview: Some(Overview {
+            file: "overview.jpg".to_string(),
+            width: 256,
+            height: 64,
+        }),
+        skipped_tiles: vec![(10, 0, 0), (10, 12, 3)],
+    };
+    let path = dir.path().join("tiles.build.json");
+    info.save(&path).unwrap();
+    assert_eq!(BuildInfo::load(&path).unwrap(), Some(info));
+}
@@ -0,0 +1,194 @@
+// SPDX-License-Identifier: MPL-2.0
+
+mod common;
+
+use common::{entries, open_image, solid_image, zoomtiler_ok};
+use image::GenericImageView;
+use zoomtiler::{
+    base_level, check_panorama_size, level_count, tile_count, LevelStrategy, TilingConfig,
+};
+
+/// Tile a single black image of the given size and return the output directory.
+fn tile_single_image(width: u32, height: u32) -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let input = solid_image(dir.path(), "input.png", width, height, [0, 0, 0]);
+    zoomtiler_ok([
+        input.as_os_str(),
+        "--output".as_ref(),
+        dir.path().join("out/tiles.dzi").as_os_str(),
+        "--format".as_ref(),
+        "png".as_ref(),
+        "--tilesize".as_ref(),
+        "65536".as_ref(),
+    ]);
+    dir
+}
+
+fn assert_levels(width: u32, height: u32, expected_levels: usize) {
+    let dir = tile_single_image(width, height);
+    let files_dir = dir.path().join("out/tiles_files");
+    assert_eq!(
+        entries(&files_dir).len(),
+        expected_levels,
+        "{}x{}",
+        width,
+        height
+    );
+    for level in 0..expected_levels {
+        assert!(files_dir.join(level.to_string()).is_dir());
+    }
+
+    // The coarsest level must be a single 1x1 tile.
+    let level_0 = files_dir.join("0");
+    assert_eq!(entries(&level_0), ["0_0.png"]);
+    let top = open_image(&level_0.join("0_0.png"));
+    assert_eq!((top.width(), top.height()), (1, 1));
+}
+
+#[test]
+fn levels_small_dimensions() {
+    assert_levels(1, 1, 1);
+    assert_levels(2, 1, 2);
+    assert_levels(1, 2, 2);
+    assert_levels(3, 1, 3);
+}
+
+#[test]
+fn levels_around_powers_of_two() {
+    assert_levels(255, 1, 9);
+    assert_levels(256, 1, 9);
+    assert_levels(257, 1, 10);
+    assert_levels(512, 1, 10);
+    assert_levels(513, 1, 11);
+    assert_levels(1000, 1, 11);
+}
+
+#[test]
+fn levels_large_dimensions() {
+    assert_levels(1, 4096, 13);
+    assert_levels(65536, 1, 17);
+    assert_levels(65537, 1, 18);
+    assert_levels(100_000, 1, 18);
+}
+
+fn config(tile_size: usize, level_strategy: LevelStrategy) -> TilingConfig {
+    TilingConfig {
+        tile_size,
+        level_strategy,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn level_strategies_on_elongated_panorama() {
+    let (width, height) = (40_000, 800);
+    // 40000x800 is a grid of 79x2 tiles of 512 pixels.
+    let count = |strategy| level_count(width, height, &config(512, strategy));
+    assert_eq!(count(LevelStrategy::MaxDim), 17);
+    assert_eq!(count(LevelStrategy::TileCount), 8);
+    assert_eq!(count(LevelStrategy::Average), 5);
+
+    // The base level keeps the DeepZoom numbering whatever the strategy.
+    assert_eq!(base_level(width, height), 16);
+}
+
+#[test]
+fn level_strategies_on_single_tile() {
+    for strategy in [
+        LevelStrategy::MaxDim,
+        LevelStrategy::TileCount,
+        LevelStrategy::Average,
+    ] {
+        let expected = if strategy == LevelStrategy::MaxDim {
+            11
+        } else {
+            1
+        };
+        assert_eq!(level_count(1000, 10, &config(1024, strategy)), expected);
+    }
+}
+
+#[test]
+fn tile_count_strategy_stops_at_a_single_tile() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = solid_image(dir.path(), "input.png", 1000, 10, [0, 0, 0]);
+    zoomtiler_ok([
+        input.as_os_str(),
+        "--output".as_ref(),
+        dir.path().join("out/tiles.dzi").as_os_str(),
+        "--format".as_ref(),
+        "png".as_ref(),
+        "--tilesize".as_ref(),
+        "256".as_ref(),
+        "--level-strategy".as_ref(),
+        "tile-count".as_ref(),
+    ]);
+    // 4 tiles wide at level 10, then 2 at level 9 and a single tile at level 8.
+    let files_dir = dir.path().join("out/tiles_files");
+    assert_eq!(entries(&files_dir), ["10", "8", "9"]);
+    assert_eq!(entries(&files_dir.join("8")), ["0_0.png"]);
+}
+
+#[test]
+fn base_only_skips_coarser_levels() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = solid_image(dir.path(), "input.png", 1000, 10, [0, 0, 0]);
+    zoomtiler_ok([
+        input.as_os_str(),
+        "--output".as_ref(),
+        dir.path().join("out/tiles.dzi").as_os_str(),
+        "--format".as_ref(),
+        "png".as_ref(),
+        "--tilesize".as_ref(),
+        "256".as_ref(),
+        "--base-only".as_ref(),
+    ]);
+    let files_dir = dir.path().join("out/tiles_files");
+    assert_eq!(entries(&files_dir), ["10"]);
+    assert_eq!(entries(&files_dir.join("10")).len(), 4);
+    let xml = std::fs::read_to_string(dir.path().join("out/tiles.dzi")).unwrap();
+    assert!(xml.contains(r#"<Size Width="1000" Height="10"/>"#));
+}
+
+#[test]
+fn discard_levels_keeps_the_coarsest_levels() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = solid_image(dir.path(), "input.png", 1000, 10, [0, 0, 0]);
+    zoomtiler_ok([
+        input.as_os_str(),
+        "--output".as_ref(),
+        dir.path().join("out/tiles.dzi").as_os_str(),
+        "--format".as_ref(),
+        "png".as_ref(),
+        "--tilesize".as_ref(),
+        "256".as_ref(),
+        "--discard-levels".as_ref(),
+        "8".as_ref(),
+    ]);
+    let files_dir = dir.path().join("out/tiles_files");
+    assert_eq!(
+        entries(&files_dir),
+        ["0", "1", "2", "3", "4", "5", "6", "7", "8"]
+    );
+    // Level 8 is still built from the discarded finer levels.
+    let tile = open_image(&files_dir.join("8/0_0.png"));
+    assert_eq!(tile.dimensions(), (250, 3));
+}
+
+#[test]
+fn tile_count_sums_all_levels() {
+    let config = config(256, LevelStrategy::MaxDim);
+    // 1050x200 has 5, 3, 2 tiles and then a single one in each of the 9 other levels.
+    assert_eq!(tile_count(1050, 200, &config), 19);
+    assert!(check_panorama_size(1050, 200, &config).is_ok());
+}
+
+#[test]
+fn huge_panoramas_are_rejected() {
+    let config = config(1, LevelStrategy::MaxDim);
+    let err = check_panorama_size(20_000, 20_000, &config).unwrap_err();
+    assert!(err.to_string().contains("20000x20000"), "{}", err);
+    assert!(err.to_string().contains("16 levels"), "{}", err);
+    let config = self::config(512, LevelStrategy::MaxDim);
+    assert!(check_panorama_size(1 << 33, 100, &config).is_err());
+}
@@ -0,0 +1,48 @@
+// SPDX-License-Identifier: MPL-2.0
+
+mod common;
+
+use common::{entries, solid_image, zoomtiler_ok};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use zoomtiler::OpenFilesLimit;
+
+#[test]
+fn open_files_limit_bounds_concurrent_guards() {
+    let limit = OpenFilesLimit::new(3);
+    let open = AtomicUsize::new(0);
+    let max_open = AtomicUsize::new(0);
+    std::thread::scope(|scope| {
+        for _ in 0..8 {
+            scope.spawn(|| {
+                for _ in 0..50 {
+                    let _guard = limit.acquire();
+                    let now_open = open.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_open.fetch_max(now_open, Ordering::SeqCst);
+                    std::thread::yield_now();
+                    open.fetch_sub(1, Ordering::SeqCst);
+                }
+            });
+        }
+    });
+    assert!(max_open.load(Ordering::SeqCst) <= 3);
+}
+
+#[test]
+fn single_open_file_still_generates_all_tiles() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = solid_image(dir.path(), "input.png", 1000, 600, [10, 20, 30]);
+    let dzi = dir.path().join("out/tiles.dzi");
+    zoomtiler_ok([
+        input.as_os_str(),
+        "--output".as_ref(),
+        dzi.as_os_str(),
+        "--tilesize".as_ref(),
+        "128".as_ref(),
+        "--threads".as_ref(),
+        "4".as_ref(),
+        "--max-open-files".as_ref(),
+        "1".as_ref(),
+    ]);
+    // 1000x600 is a grid of 8x5 tiles of 128 pixels.
+    assert_eq!(entries(&dir.path().join("out/tiles_files/10")).len(), 40);
+}
@@ -0,0 +1,139 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use image::{Rgb, RgbImage};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use zoomtiler::{
+    generate_tiles, Borders, InputImage, Rotation, Tile, TileOrder, TileSink, TilingConfig,
+};
+
+#[test]
+fn tiles_are_sorted_around_the_center() {
+    let columns = [0, 1, 2, 3, 4];
+    let sorted = |order: TileOrder| order.sort(&columns, 0..3);
+    assert_eq!(
+        sorted(TileOrder::Column)[..4],
+        [(0, 0), (0, 1), (0, 2), (1, 0)]
+    );
+    assert_eq!(
+        sorted(TileOrder::Row)[..6],
+        [(0, 0), (1, 0), (2, 0), (3, 0), (4, 0), (0, 1)]
+    );
+    assert_eq!(
+        sorted(TileOrder::CenterOut)[..5],
+        [(2, 1), (1, 1), (2, 0), (2, 2), (3, 1)]
+    );
+    assert_eq!(
+        sorted(TileOrder::Spiral),
+        [
+            (2, 1),
+            (2, 0),
+            (3, 0),
+            (3, 1),
+            (3, 2),
+            (2, 2),
+            (1, 2),
+            (1, 1),
+            (1, 0),
+            (4, 0),
+            (4, 1),
+            (4, 2),
+            (0, 2),
+            (0, 1),
+            (0, 0)
+        ]
+    );
+    // The center is the one of the given tiles.
+    assert_eq!(TileOrder::CenterOut.sort(&[6, 7, 8], 4..5)[0], (7, 4));
+}
+
+/// Tiles kept in memory, with the order in which they were written.
+#[derive(Default)]
+struct OrderSink {
+    tiles: Mutex<HashMap<(usize, usize, usize), RgbImage>>,
+    writes: Mutex<Vec<(usize, usize, usize)>>,
+}
+
+impl TileSink<Rgb<u8>> for OrderSink {
+    fn write_tile(
+        &self,
+        level: usize,
+        tx: usize,
+        ty: usize,
+        tile: &RgbImage,
+    ) -> anyhow::Result<()> {
+        self.tiles
+            .lock()
+            .unwrap()
+            .insert((level, tx, ty), tile.clone());
+        self.writes.lock().unwrap().push((level, tx, ty));
+        Ok(())
+    }
+    fn read_tile(
+        &self,
+        level: usize,
+        tx: usize,
+        ty: usize,
+    ) -> anyhow::Result<Option<Tile<Rgb<u8>>>> {
+        Ok(self.tiles.lock().unwrap().get(&(level, tx, ty)).cloned())
+    }
+}
+
+/// Tile a 10x6 image into 2 pixels tiles in the given order, with a single thread.
+fn tile_in_order(tile_order: TileOrder) -> OrderSink {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("gradient.png");
+    RgbImage::from_fn(10, 6, |x, y| Rgb([x as u8 * 20, y as u8 * 40, 0]))
+        .save(&path)
+        .unwrap();
+    let inputs = [InputImage {
+        path,
+        width: 10,
+        height: 6,
+        offset_y: 0,
+        pad_left: 0,
+        bytes: None,
+        rotation: Rotation::None,
+        resize: None,
+        trim: Borders::default(),
+    }];
+    let config = TilingConfig {
+        tile_size: 2,
+        tile_order,
+        ..Default::default()
+    };
+    let sink = OrderSink::default();
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .unwrap();
+    pool.install(|| generate_tiles(&inputs, &config, &sink))
+        .unwrap();
+    sink
+}
+
+#[test]
+fn sink_receives_the_tiles_in_order() {
+    let column = tile_in_order(TileOrder::Column);
+    let center_out = tile_in_order(TileOrder::CenterOut);
+    let writes = center_out.writes.lock().unwrap();
+    // The base level 4 comes first, from its center.
+    let base_tiles = TileOrder::CenterOut.sort(&[0, 1, 2, 3, 4], 0..3);
+    let base_writes: Vec<_> = base_tiles.iter().map(|&(tx, ty)| (4, tx, ty)).collect();
+    assert_eq!(writes[0], (4, 2, 1));
+    assert_eq!(writes[..15], base_writes[..]);
+    // Level 3 has 3x2 tiles, whose center is between its two middle tiles.
+    assert_eq!(writes[15..17], [(3, 1, 0), (3, 1, 1)]);
+
+    // Only the order differs.
+    assert_eq!(
+        *column.tiles.lock().unwrap(),
+        *center_out.tiles.lock().unwrap()
+    );
+    let mut column_writes = column.writes.lock().unwrap().clone();
+    let mut center_out_writes = writes.clone();
+    assert_ne!(column_writes, center_out_writes);
+    column_writes.sort();
+    center_out_writes.sort();
+    assert_eq!(column_writes, center_out_writes);
+}
@@ -0,0 +1,223 @@
+// SPDX-License-Identifier: MPL-2.0
+
+mod common;
+
+use common::solid_image;
+use image::{Rgb, RgbImage};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use zoomtiler::{
+    generate_tiles, Borders, DebugGrid, DownsampleMode, InputImage, Rotation, Tile, TileSink,
+    TilingConfig,
+};
+
+const BASE_LEVEL: usize = 3;
+
+/// Tiles kept in memory, except for some base tiles which are dropped
+/// to simulate tiles missing at the edges of a level.
+struct MemorySink {
+    tiles: Mutex<HashMap<(usize, usize, usize), RgbImage>>,
+    dropped: Vec<(usize, usize)>,
+}
+
+impl TileSink<Rgb<u8>> for MemorySink {
+    fn write_tile(
+        &self,
+        level: usize,
+        tx: usize,
+        ty: usize,
+        tile: &RgbImage,
+    ) -> anyhow::Result<()> {
+        if level != BASE_LEVEL || !self.dropped.contains(&(tx, ty)) {
+            let mut tiles = self.tiles.lock().unwrap();
+            tiles.insert((level, tx, ty), tile.clone());
+        }
+        Ok(())
+    }
+    fn read_tile(
+        &self,
+        level: usize,
+        tx: usize,
+        ty: usize,
+    ) -> anyhow::Result<Option<Tile<Rgb<u8>>>> {
+        Ok(self.tiles.lock().unwrap().get(&(level, tx, ty)).cloned())
+    }
+}
+
+/// Tile a 7x7 red image into a 2x2 grid of 4 pixels tiles,
+/// with edge tiles of 3 pixels, after dropping some of the base tiles.
+fn tile_with_dropped(dropped: &[(usize, usize)]) -> anyhow::Result<MemorySink> {
+    let dir = tempfile::tempdir().unwrap();
+    let inputs = [InputImage {
+        path: solid_image(dir.path(), "input.png", 7, 7, [255, 0, 0]),
+        width: 7,
+        height: 7,
+        offset_y: 0,
+        pad_left: 0,
+        bytes: None,
+        rotation: Rotation::None,
+        resize: None,
+        trim: Borders::default(),
+    }];
+    let config = TilingConfig {
+        tile_size: 4,
+        ..Default::default()
+    };
+    let sink = MemorySink {
+        tiles: Mutex::default(),
+        dropped: dropped.to_vec(),
+    };
+    generate_tiles(&inputs, &config, &sink)?;
+    Ok(sink)
+}
+
+fn tile_size(sink: &MemorySink, level: usize) -> (u32, u32) {
+    sink.tiles.lock().unwrap()[&(level, 0, 0)].dimensions()
+}
+
+#[test]
+fn complete_grid() {
+    let sink = tile_with_dropped(&[]).unwrap();
+    assert_eq!(tile_size(&sink, 2), (4, 4));
+    assert_eq!(tile_size(&sink, 0), (1, 1));
+    let tiles = sink.tiles.lock().unwrap();
+    assert!(tiles[&(2, 0, 0)].pixels().all(|p| *p == Rgb([255, 0, 0])));
+}
+
+#[test]
+fn every_combination_of_missing_neighbors() {
+    // Missing top right, bottom left and bottom right tiles, in all combinations.
+    let neighbors = [(1, 0), (0, 1), (1, 1)];
+    for combination in 1..8 {
+        let dropped: Vec<(usize, usize)> = (0..3)
+            .filter(|i| combination & (1 << i) != 0)
+            .map(|i| neighbors[i])
+            .collect();
+        let sink = tile_with_dropped(&dropped).unwrap();
+        let kept = |tile| !dropped.contains(&tile);
+        // The right column and bottom row keep the size of their remaining tile, if any.
+        let width: u32 = 4 + if kept((1, 0)) || kept((1, 1)) { 3 } else { 0 };
+        let height: u32 = 4 + if kept((0, 1)) || kept((1, 1)) { 3 } else { 0 };
+        assert_eq!(
+            tile_size(&sink, 2),
+            (width.div_ceil(2), height.div_ceil(2)),
+            "dropped {:?}",
+            dropped
+        );
+        assert_eq!(tile_size(&sink, 0), (1, 1), "dropped {:?}", dropped);
+        // Pixels only come from the loaded tiles, so the top left one stays red.
+        let tiles = sink.tiles.lock().unwrap();
+        assert_eq!(tiles[&(2, 0, 0)].get_pixel(0, 0), &Rgb([255, 0, 0]));
+    }
+}
+
+#[test]
+fn missing_top_left_tile_is_an_error() {
+    let err = tile_with_dropped(&[(0, 0)]).err().unwrap();
+    assert_eq!(err.to_string(), "Missing tile (0, 0) of level 3");
+}
+
+/// Single pixel of the coarsest level of a 2x2 image of the given pixels.
+fn downsample_2x2(values: [[u8; 3]; 4], downsample: DownsampleMode) -> Rgb<u8> {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("input.png");
+    RgbImage::from_fn(2, 2, |x, y| Rgb(values[(2 * y + x) as usize]))
+        .save(&path)
+        .unwrap();
+    let inputs = [InputImage {
+        path,
+        width: 2,
+        height: 2,
+        offset_y: 0,
+        pad_left: 0,
+        bytes: None,
+        rotation: Rotation::None,
+        resize: None,
+        trim: Borders::default(),
+    }];
+    let config = TilingConfig {
+        tile_size: 4,
+        downsample,
+        ..Default::default()
+    };
+    let sink = MemorySink {
+        tiles: Mutex::default(),
+        dropped: Vec::new(),
+    };
+    generate_tiles(&inputs, &config, &sink).unwrap();
+    let tiles = sink.tiles.lock().unwrap();
+    *tiles[&(0, 0, 0)].get_pixel(0, 0)
+}
+
+#[test]
+fn half_resolution_rounds_to_nearest() {
+    // Means of 0.5, 1.75 and 10.5, which truncation would bring down to 0, 1 and 10.
+    let values = [[0, 1, 10], [0, 2, 11], [0, 2, 10], [2, 2, 11]];
+    assert_eq!(
+        downsample_2x2(values, DownsampleMode::Mean),
+        Rgb([1, 2, 11])
+    );
+}
+
+#[test]
+fn downsample_modes_reduce_each_channel() {
+    // A black line on white, and 4 different values.
+    let values = [[0, 255, 10], [255, 255, 40], [255, 0, 20], [255, 255, 30]];
+    let reduce = |mode| downsample_2x2(values, mode);
+    assert_eq!(reduce(DownsampleMode::Mean), Rgb([191, 191, 25]));
+    assert_eq!(reduce(DownsampleMode::Median), Rgb([255, 255, 25]));
+    assert_eq!(reduce(DownsampleMode::Darkest), Rgb([0, 0, 10]));
+    assert_eq!(reduce(DownsampleMode::Lightest), Rgb([255, 255, 40]));
+}
+
+/// Tile two red images of 3 and 4 pixels wide into 4 pixels tiles, with a debug grid.
+fn tile_with_debug_grid(debug_grid: DebugGrid) -> MemorySink {
+    let dir = tempfile::tempdir().unwrap();
+    let input = |name, width| InputImage {
+        path: solid_image(dir.path(), name, width as u32, 7, [255, 0, 0]),
+        width,
+        height: 7,
+        offset_y: 0,
+        pad_left: 0,
+        bytes: None,
+        rotation: Rotation::None,
+        resize: None,
+        trim: Borders::default(),
+    };
+    let inputs = [input("1.png", 3), input("2.png", 4)];
+    let config = TilingConfig {
+        tile_size: 4,
+        debug_grid,
+        ..Default::default()
+    };
+    let sink = MemorySink {
+        tiles: Mutex::default(),
+        dropped: Vec::new(),
+    };
+    generate_tiles(&inputs, &config, &sink).unwrap();
+    sink
+}
+
+#[test]
+fn debug_grid_marks_tile_borders_and_seams_of_the_base_level() {
+    let sink = tile_with_debug_grid(DebugGrid::BaseLevel);
+    let tiles = sink.tiles.lock().unwrap();
+    let base = &tiles[&(BASE_LEVEL, 0, 0)];
+    assert_eq!(base.get_pixel(1, 0), &Rgb([255, 0, 255]));
+    assert_eq!(base.get_pixel(1, 1), &Rgb([255, 0, 0]));
+    // The first image ends at column 2 and the second one starts at column 3.
+    assert_eq!(base.get_pixel(2, 1), &Rgb([0, 255, 0]));
+    assert_eq!(base.get_pixel(3, 1), &Rgb([0, 255, 0]));
+    assert!(tiles[&(2, 0, 0)].pixels().all(|p| *p == Rgb([255, 0, 0])));
+}
+
+#[test]
+fn debug_grid_of_all_levels_is_downscaled() {
+    let sink = tile_with_debug_grid(DebugGrid::AllLevels);
+    let tiles = sink.tiles.lock().unwrap();
+    assert_eq!(
+        tiles[&(BASE_LEVEL, 0, 0)].get_pixel(1, 0),
+        &Rgb([255, 0, 255])
+    );
+    assert!(tiles[&(2, 0, 0)].pixels().any(|p| *p != Rgb([255, 0, 0])));
+}
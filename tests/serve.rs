@@ -0,0 +1,49 @@
+// SPDX-License-Identifier: MPL-2.0
+
+mod common;
+
+use common::{solid_image, zoomtiler_ok};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use zoomtiler::PreviewServer;
+
+/// Send a GET request and return the status line and the body of the response.
+fn get(addr: SocketAddr, path: &str) -> (String, Vec<u8>) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    let header_end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    let header = String::from_utf8_lossy(&response[..header_end]).into_owned();
+    let status = header.lines().next().unwrap().to_string();
+    (status, response[header_end + 4..].to_vec())
+}
+
+#[test]
+fn serves_the_viewer_and_the_tiles() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = solid_image(dir.path(), "input.png", 1000, 300, [10, 20, 30]);
+    let dzi = dir.path().join("out/tiles.dzi");
+    zoomtiler_ok([input.as_os_str(), "--output".as_ref(), dzi.as_os_str()]);
+    let server = PreviewServer::bind(&dzi, "127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = server.local_addr().unwrap();
+    std::thread::spawn(move || server.run());
+
+    let (status, index) = get(addr, "/");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert!(String::from_utf8_lossy(&index).contains(r#"tileSources: "tiles.dzi""#));
+    let (status, xml) = get(addr, "/tiles.dzi");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(xml, std::fs::read(&dzi).unwrap());
+    let tile = dir.path().join("out/tiles_files/10/0_0.jpg");
+    assert_eq!(
+        get(addr, "/tiles_files/10/0_0.jpg").1,
+        std::fs::read(tile).unwrap()
+    );
+    assert_eq!(
+        get(addr, "/tiles_files/10/9_9.jpg").0,
+        "HTTP/1.1 404 Not Found"
+    );
+    // Files outside of the tileset directory are not served.
+    assert_eq!(get(addr, "/../input.png").0, "HTTP/1.1 404 Not Found");
+}
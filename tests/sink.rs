@@ -0,0 +1,35 @@
+// SPDX-License-Identifier: MPL-2.0
+
+use image::{Rgb, RgbImage};
+use std::time::Duration;
+use zoomtiler::{FsTileSink, TileEncoding, TileSink};
+
+#[test]
+fn transient_write_errors_are_retried() {
+    let dir = tempfile::tempdir().unwrap();
+    let sink = FsTileSink::new(dir.path(), TileEncoding::new("png")).with_io_retries(5);
+    // A directory in place of the tile fails the writes until it is removed.
+    let blocker = dir.path().join("3/0_0.png");
+    std::fs::create_dir_all(&blocker).unwrap();
+    let tile = RgbImage::from_pixel(4, 4, Rgb([1, 2, 3]));
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            std::thread::sleep(Duration::from_millis(150));
+            std::fs::remove_dir(&blocker).unwrap();
+        });
+        sink.write_tile(3, 0, 0, &tile).unwrap();
+    });
+    assert!(blocker.is_file());
+}
+
+#[test]
+fn permanent_write_errors_fail_with_the_tile() {
+    let dir = tempfile::tempdir().unwrap();
+    let sink = FsTileSink::new(dir.path(), TileEncoding::new("png")).with_io_retries(1);
+    std::fs::create_dir_all(dir.path().join("3/1_2.png")).unwrap();
+    let tile = RgbImage::from_pixel(4, 4, Rgb([1, 2, 3]));
+    let err = sink.write_tile(3, 1, 2, &tile).unwrap_err();
+    let message = err.to_string();
+    assert!(message.contains("tile (1, 2) of level 3"), "{}", message);
+    assert!(message.contains("1_2.png"), "{}", message);
+}
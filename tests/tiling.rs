@@ -0,0 +1,1186 @@
+// SPDX-License-Identifier: MPL-2.0
+
+mod common;
+
+use common::{
+    assert_tile_grids, entries, expected_grids, open_image, solid_image, zoomtiler, zoomtiler_ok,
+};
+use image::{GenericImageView, Rgb};
+use std::ffi::OsString;
+use std::path::PathBuf;
+use zoomtiler::{read_dpi, XmlElement};
+
+const RED: [u8; 3] = [255, 0, 0];
+const GREEN: [u8; 3] = [0, 255, 0];
+const BLUE: [u8; 3] = [0, 0, 255];
+
+/// Tile a red, a green and a blue slice, of widths 300, 500 and 250, into png tiles of 256.
+fn tile_three_slices(dir: &tempfile::TempDir) -> PathBuf {
+    let inputs = [
+        solid_image(dir.path(), "1.png", 300, 200, RED),
+        solid_image(dir.path(), "2.png", 500, 200, GREEN),
+        solid_image(dir.path(), "3.png", 250, 200, BLUE),
+    ];
+    let dzi = dir.path().join("out/tiles.dzi");
+    let mut args: Vec<OsString> = inputs.iter().map(|p| p.clone().into()).collect();
+    args.extend(["--output".into(), dzi.clone().into()]);
+    args.extend(["--format", "png", "--tilesize", "256"].map(Into::into));
+    zoomtiler_ok(args);
+    dzi
+}
+
+#[test]
+fn dzi_describes_the_whole_panorama() {
+    let dir = tempfile::tempdir().unwrap();
+    let dzi = tile_three_slices(&dir);
+    let xml = std::fs::read_to_string(dzi).unwrap();
+    assert!(xml.contains(r#"TileSize="256" Overlap="0" Format="png""#));
+    assert!(xml.contains(r#"<Size Width="1050" Height="200"/>"#));
+}
+
+#[test]
+fn display_rects_are_written_in_the_dzi() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = solid_image(dir.path(), "1.png", 300, 200, RED);
+    let dzi = dir.path().join("out/tiles.dzi");
+    let args = |rects: &str| -> Vec<OsString> {
+        let mut args = vec![input.clone().into(), "--output".into(), dzi.clone().into()];
+        args.extend(["--display-rect".into(), rects.into()]);
+        args
+    };
+    zoomtiler_ok(args("0,0,100,50;200,100,100,100"));
+    let xml = std::fs::read_to_string(&dzi).unwrap();
+    assert!(
+        xml.ends_with(concat!(
+            r#"<Size Width="300" Height="200"/><DisplayRects>"#,
+            r#"<DisplayRect MinLevel="0" MaxLevel="9"><Rect X="0" Y="0" Width="100" Height="50"/></DisplayRect>"#,
+            r#"<DisplayRect MinLevel="0" MaxLevel="9"><Rect X="200" Y="100" Width="100" Height="100"/></DisplayRect>"#,
+            r#"</DisplayRects></Image>"#
+        )),
+        "{}",
+        xml
+    );
+
+    // Rectangles must be inside the panorama.
+    let output = zoomtiler(args("250,0,100,10"));
+    assert!(!output.status.success());
+}
+
+#[test]
+fn stdout_dzi_only_prints_the_descriptor() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = solid_image(dir.path(), "1.png", 300, 200, RED);
+    let output = zoomtiler_ok([
+        input.as_os_str(),
+        "--output".as_ref(),
+        dir.path().join("out/tiles.dzi").as_os_str(),
+        "--format".as_ref(),
+        "png".as_ref(),
+        "--tilesize".as_ref(),
+        "128".as_ref(),
+        "--stdout-dzi".as_ref(),
+    ]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        concat!(
+            r#"<?xml version="1.0" encoding="UTF-8"?><Image xmlns="http://schemas.microsoft.com/deepzoom/2008" "#,
+            r#"TileSize="128" Overlap="0" Format="png"><Size Width="300" Height="200"/></Image>"#,
+            "\n"
+        )
+    );
+    assert!(!dir.path().join("out").exists());
+}
+
+#[test]
+fn stitch_only_saves_the_panorama_without_tiles() {
+    let dir = tempfile::tempdir().unwrap();
+    let inputs = [
+        solid_image(dir.path(), "1.png", 300, 200, RED),
+        solid_image(dir.path(), "2.png", 500, 150, GREEN),
+    ];
+    let stitched = dir.path().join("flat/panorama.png");
+    let mut args: Vec<OsString> = inputs.iter().map(|p| p.clone().into()).collect();
+    args.extend(["--output".into(), dir.path().join("out/tiles.dzi").into()]);
+    args.extend([
+        "--tilesize".into(),
+        "128".into(),
+        "--gap".into(),
+        "10".into(),
+    ]);
+    args.extend(["--stitch-only".into(), stitched.clone().into()]);
+    zoomtiler_ok(args);
+    assert!(!dir.path().join("out").exists());
+
+    let img = open_image(&stitched).to_rgb8();
+    assert_eq!(img.dimensions(), (810, 150));
+    assert_eq!(*img.get_pixel(299, 149), Rgb(RED));
+    assert_eq!(*img.get_pixel(305, 0), Rgb([255, 255, 255]));
+    assert_eq!(*img.get_pixel(310, 100), Rgb(GREEN));
+    assert_eq!(*img.get_pixel(809, 149), Rgb(GREEN));
+}
+
+#[test]
+fn stitch_only_rejects_jpg_panoramas_too_wide_for_the_format() {
+    let dir = tempfile::tempdir().unwrap();
+    let inputs = [
+        solid_image(dir.path(), "1.png", 30, 20, RED),
+        solid_image(dir.path(), "2.png", 30, 20, GREEN),
+    ];
+    let stitched = dir.path().join("panorama.jpg");
+    let mut args: Vec<OsString> = inputs.iter().map(|p| p.clone().into()).collect();
+    args.extend(["--output".into(), dir.path().join("out/tiles.dzi").into()]);
+    args.extend(["--gap".into(), "70000".into()]);
+    args.extend(["--stitch-only".into(), stitched.clone().into()]);
+    let output = zoomtiler(args);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("70060x20 pixels is too large for a jpg image"),
+        "{}",
+        stderr
+    );
+    assert!(!stitched.exists());
+}
+
+#[test]
+fn overview_is_the_finest_level_that_fits() {
+    let dir = tempfile::tempdir().unwrap();
+    let inputs = [
+        solid_image(dir.path(), "1.png", 300, 200, RED),
+        solid_image(dir.path(), "2.png", 500, 200, GREEN),
+        solid_image(dir.path(), "3.png", 250, 200, BLUE),
+    ];
+    let overview = |extra: &[&str]| {
+        let mut args: Vec<OsString> = inputs.iter().map(|p| p.clone().into()).collect();
+        args.extend(["--output".into(), dir.path().join("out/tiles.dzi").into()]);
+        args.extend(["--tilesize", "256", "--overview", "100"].map(Into::into));
+        args.extend(extra.iter().map(Into::into));
+        zoomtiler_ok(args);
+        open_image(&dir.path().join("out/overview.jpg")).to_rgb8()
+    };
+    // Level 7 is 66x13, level 8 is 132x25.
+    let img = overview(&[]);
+    assert_eq!(img.dimensions(), (66, 13));
+    let center = |img: &image::RgbImage, x| img.get_pixel(x, 6).0;
+    assert!(center(&img, 5)[0] > 200 && center(&img, 40)[1] > 200 && center(&img, 62)[2] > 200);
+    let info = std::fs::read_to_string(dir.path().join("out/tiles.build.json")).unwrap();
+    assert!(
+        info.contains(concat!(
+            r#""overview": "overview.jpg","#,
+            "\n",
+            r#"  "overview_width": 66,"#,
+            "\n",
+            r#"  "overview_height": 13"#
+        )),
+        "{}",
+        info
+    );
+
+    // Levels stop at level 8, which is downsampled once more.
+    let img = overview(&["--level-strategy", "tile-count"]);
+    assert_eq!(img.dimensions(), (66, 13));
+    assert!(center(&img, 40)[1] > 200);
+}
+
+#[test]
+fn sort_orders_inputs_with_their_offsets() {
+    let dir = tempfile::tempdir().unwrap();
+    let inputs = [
+        solid_image(dir.path(), "img10.png", 100, 200, BLUE),
+        solid_image(dir.path(), "img2.png", 100, 200, GREEN),
+        solid_image(dir.path(), "img1.png", 100, 200, RED),
+    ];
+    let dzi = dir.path().join("out/tiles.dzi");
+    // Colors of the three inputs from left to right, at the top and 10 rows below.
+    let tile = |order: &str| {
+        let mut args: Vec<OsString> = inputs.iter().map(|p| p.clone().into()).collect();
+        args.extend(["--output".into(), dzi.clone().into()]);
+        args.extend(
+            [
+                "--format",
+                "png",
+                "--offsets",
+                "5,0,0",
+                "--verbose",
+                "--sort",
+                order,
+            ]
+            .map(Into::into),
+        );
+        let output = zoomtiler_ok(args);
+        let base = open_image(&dir.path().join("out/tiles_files/9/0_0.png")).to_rgb8();
+        let colors: Vec<_> = [(50, 0), (150, 0), (250, 0), (50, 10), (150, 10), (250, 10)]
+            .iter()
+            .map(|&(x, y)| base.get_pixel(x, y).0)
+            .collect();
+        (colors, String::from_utf8_lossy(&output.stderr).into_owned())
+    };
+    let black = [0; 3];
+
+    let (colors, stderr) = tile("natural");
+    assert_eq!(colors, [RED, GREEN, black, RED, GREEN, BLUE]);
+    let expected = format!(
+        "Inputs, from left to right:\n  0: {}\n  1: {}\n  2: {}\n",
+        inputs[2].display(),
+        inputs[1].display(),
+        inputs[0].display()
+    );
+    assert!(stderr.contains(&expected), "{}", stderr);
+
+    let (colors, _) = tile("lexical");
+    assert_eq!(colors, [RED, black, GREEN, RED, BLUE, GREEN]);
+    let (colors, _) = tile("none");
+    assert_eq!(colors, [black, GREEN, RED, BLUE, GREEN, RED]);
+}
+
+#[test]
+fn lexical_sort_compares_whole_paths() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("a")).unwrap();
+    let inputs = [
+        solid_image(&dir.path().join("a"), "b.png", 100, 200, RED),
+        solid_image(dir.path(), "a-b.png", 100, 200, GREEN),
+    ];
+    zoomtiler_ok([
+        inputs[0].as_os_str(),
+        inputs[1].as_os_str(),
+        "--output".as_ref(),
+        dir.path().join("out/tiles.dzi").as_os_str(),
+        "--format".as_ref(),
+        "png".as_ref(),
+        "--sort".as_ref(),
+        "lexical".as_ref(),
+    ]);
+    // "-" comes before "/", even though the a directory comes before the a-b.png file.
+    let base = open_image(&dir.path().join("out/tiles_files/8/0_0.png")).to_rgb8();
+    assert_eq!(base.get_pixel(50, 0).0, GREEN);
+    assert_eq!(base.get_pixel(150, 0).0, RED);
+}
+
+#[test]
+fn xml_attributes_are_escaped() {
+    let element = XmlElement::new("Image")
+        .attribute("Format", r#"a"<b>&'c"#)
+        .child(XmlElement::new("Size"));
+    assert_eq!(
+        element.to_document(),
+        r#"<?xml version="1.0" encoding="UTF-8"?><Image Format="a&quot;&lt;b&gt;&amp;&apos;c"><Size/></Image>"#
+    );
+}
+
+#[test]
+fn every_level_has_the_full_tile_grid() {
+    let dir = tempfile::tempdir().unwrap();
+    tile_three_slices(&dir);
+    let grids = expected_grids(1050, 200, 256);
+    assert_eq!(grids.len(), 12);
+    assert_tile_grids(&dir.path().join("out/tiles_files"), "png", &grids);
+}
+
+#[test]
+fn images_are_stitched_left_to_right() {
+    let dir = tempfile::tempdir().unwrap();
+    tile_three_slices(&dir);
+    let base = dir.path().join("out/tiles_files/11");
+    let pixel = |tile: &str, x: u32| open_image(&base.join(tile)).to_rgb8().get_pixel(x, 0).0;
+
+    // First tile is fully red.
+    assert_eq!(pixel("0_0.png", 0), RED);
+    assert_eq!(pixel("0_0.png", 255), RED);
+    // The red/green seam is at x = 300, in the second tile.
+    assert_eq!(pixel("1_0.png", 43), RED);
+    assert_eq!(pixel("1_0.png", 44), GREEN);
+    // The green/blue seam is at x = 800, in the fourth tile.
+    assert_eq!(pixel("3_0.png", 31), GREEN);
+    assert_eq!(pixel("3_0.png", 32), BLUE);
+    // The last tile only keeps the remaining 26 columns of blue.
+    let last = open_image(&base.join("4_0.png"));
+    assert_eq!((last.width(), last.height()), (26, 200));
+    assert_eq!(last.to_rgb8().get_pixel(25, 199), &Rgb(BLUE));
+}
+
+#[test]
+fn gaps_are_filled_with_the_gap_color() {
+    let dir = tempfile::tempdir().unwrap();
+    let inputs = [
+        solid_image(dir.path(), "1.png", 300, 200, RED),
+        solid_image(dir.path(), "2.png", 200, 200, GREEN),
+    ];
+    let dzi = dir.path().join("out/tiles.dzi");
+    let mut args: Vec<OsString> = inputs.iter().map(|p| p.clone().into()).collect();
+    args.extend(["--output".into(), dzi.clone().into()]);
+    args.extend(
+        [
+            "--format",
+            "png",
+            "--tilesize",
+            "256",
+            "--gap",
+            "600",
+            "--gap-color",
+            "0000ff",
+        ]
+        .map(Into::into),
+    );
+    zoomtiler_ok(args);
+    let xml = std::fs::read_to_string(dzi).unwrap();
+    assert!(
+        xml.contains(r#"<Size Width="1100" Height="200"/>"#),
+        "{}",
+        xml
+    );
+
+    let base = dir.path().join("out/tiles_files/11");
+    let tile = |name: &str| open_image(&base.join(name)).to_rgb8();
+    // The gap covers the columns 300 to 900, the third tile is entirely in it.
+    assert!(tile("2_0.png").pixels().all(|p| p.0 == BLUE));
+    let (second, fourth) = (tile("1_0.png"), tile("3_0.png"));
+    for y in [0, 199] {
+        assert_eq!(second.get_pixel(43, y).0, RED);
+        assert_eq!(second.get_pixel(44, y).0, BLUE);
+        assert_eq!(fourth.get_pixel(131, y).0, BLUE);
+        assert_eq!(fourth.get_pixel(132, y).0, GREEN);
+    }
+}
+
+#[test]
+fn coarsest_levels_average_all_slices() {
+    let dir = tempfile::tempdir().unwrap();
+    tile_three_slices(&dir);
+    let top = open_image(&dir.path().join("out/tiles_files/0/0_0.png")).to_rgb8();
+    assert_eq!(top.dimensions(), (1, 1));
+    let [r, g, b] = top.get_pixel(0, 0).0;
+    assert!(r > 0 && g > 0 && b > 0, "{:?}", (r, g, b));
+}
+
+#[test]
+fn input_smaller_than_a_tile_has_one_tile_per_level() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = solid_image(dir.path(), "small.png", 300, 200, RED);
+    let dzi = dir.path().join("out/tiles.dzi");
+    let args: [OsString; 5] = [
+        input.into(),
+        "--output".into(),
+        dzi.clone().into(),
+        "--format".into(),
+        "png".into(),
+    ];
+    zoomtiler_ok(args);
+    let xml = std::fs::read_to_string(dzi).unwrap();
+    assert!(xml.contains(r#"<Size Width="300" Height="200"/>"#));
+
+    // DeepZoom viewers expect all levels down to a single pixel,
+    // each of them being a single tile here.
+    let files_dir = dir.path().join("out/tiles_files");
+    let grids = expected_grids(300, 200, 512);
+    assert_eq!(grids.len(), 10);
+    assert!(grids.iter().all(|grid| *grid == (1, 1)));
+    assert_tile_grids(&files_dir, "png", &grids);
+    let (mut width, mut height) = (300, 200);
+    for level in (0..10).rev() {
+        let tile = open_image(&files_dir.join(format!("{}/0_0.png", level)));
+        assert_eq!((tile.width(), tile.height()), (width, height));
+        assert_eq!(tile.to_rgb8().get_pixel(0, 0), &Rgb(RED));
+        width = width.div_ceil(2);
+        height = height.div_ceil(2);
+    }
+}
+
+#[test]
+fn feather_blends_across_seams() {
+    let dir = tempfile::tempdir().unwrap();
+    let inputs = [
+        solid_image(dir.path(), "1.png", 300, 200, RED),
+        solid_image(dir.path(), "2.png", 500, 200, BLUE),
+    ];
+    let mut args: Vec<OsString> = inputs.iter().map(|p| p.clone().into()).collect();
+    args.extend(["--output".into(), dir.path().join("out/tiles.dzi").into()]);
+    args.extend(["--format", "png", "--tilesize", "256", "--feather", "8"].map(Into::into));
+    zoomtiler_ok(args);
+
+    // The seam at x = 300 is blended from x = 296 to 303, in the second tile.
+    let tile = open_image(&dir.path().join("out/tiles_files/10/1_0.png")).to_rgb8();
+    assert_eq!(tile.get_pixel(39, 100), &Rgb(RED));
+    assert_eq!(tile.get_pixel(40, 100), &Rgb([239, 0, 16]));
+    assert_eq!(tile.get_pixel(43, 100), &Rgb([143, 0, 112]));
+    assert_eq!(tile.get_pixel(47, 100), &Rgb([16, 0, 239]));
+    assert_eq!(tile.get_pixel(48, 100), &Rgb(BLUE));
+}
+
+#[test]
+fn variants_encode_the_same_tiles_with_each_quality() {
+    let dir = tempfile::tempdir().unwrap();
+    let inputs = [
+        solid_image(dir.path(), "1.png", 300, 200, RED),
+        solid_image(dir.path(), "2.png", 500, 200, GREEN),
+    ];
+    let tile = |dzi: &str, extra_args: &[&str]| {
+        let mut args: Vec<OsString> = inputs.iter().map(|p| p.clone().into()).collect();
+        args.extend(["--output".into(), dir.path().join(dzi).into()]);
+        args.extend(["--tilesize", "256", "--feather", "64"].map(Into::into));
+        args.extend(extra_args.iter().map(Into::into));
+        zoomtiler_ok(args);
+    };
+    tile("variants/tiles.dzi", &["--variants", "hi:95,lo:20"]);
+    tile("hi/tiles.dzi", &["--quality", "95"]);
+    tile("lo/tiles.dzi", &["--quality", "20"]);
+
+    let variants = dir.path().join("variants");
+    assert!(variants.join("tiles_hi.dzi").is_file());
+    assert!(variants.join("tiles_lo.dzi").is_file());
+    assert!(!variants.join("tiles.dzi").exists());
+    // Base tiles are encoded from the same pixels as when tiling with a single quality.
+    for variant in ["hi", "lo"] {
+        for tx in 0..4 {
+            let tile_path = format!("10/{}_0.jpg", tx);
+            let variant_dir = variants.join(format!("tiles_{}_files", variant));
+            let single_dir = dir.path().join(variant).join("tiles_files");
+            assert_eq!(
+                std::fs::read(variant_dir.join(&tile_path)).unwrap(),
+                std::fs::read(single_dir.join(&tile_path)).unwrap()
+            );
+        }
+    }
+}
+
+#[test]
+fn tile_name_template_is_used_for_all_levels() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = solid_image(dir.path(), "1.png", 600, 200, RED);
+    let args: [OsString; 8] = [
+        input.into(),
+        "--output".into(),
+        dir.path().join("out/tiles.dzi").into(),
+        "--tilesize".into(),
+        "256".into(),
+        "--tile-name-template".into(),
+        "tile-{x:03}-{y}-{z}.{ext}".into(),
+        "--grayscale".into(),
+    ];
+    zoomtiler_ok(args);
+    let files_dir = dir.path().join("out/tiles_files");
+    assert_eq!(
+        entries(&files_dir.join("10")),
+        [
+            "tile-000-0-10.jpg",
+            "tile-001-0-10.jpg",
+            "tile-002-0-10.jpg"
+        ]
+    );
+    // Coarser levels are computed by reading back the tiles with the same names.
+    assert_eq!(entries(&files_dir.join("0")), ["tile-000-0-0.jpg"]);
+}
+
+#[test]
+fn tile_name_template_needs_both_coordinates() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = solid_image(dir.path(), "1.png", 600, 200, RED);
+    let args: [OsString; 5] = [
+        input.into(),
+        "--output".into(),
+        dir.path().join("out/tiles.dzi").into(),
+        "--tile-name-template".into(),
+        "tile-{x}.{ext}".into(),
+    ];
+    let output = zoomtiler(args);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Invalid --tile-name-template"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn autotrim_removes_uniform_borders() {
+    let dir = tempfile::tempdir().unwrap();
+    // Slices of 300x200 and 500x200 inside nearly black borders.
+    let bordered = |name: &str, width: u32, height: u32, color: [u8; 3]| {
+        let path = dir.path().join(name);
+        image::RgbImage::from_fn(width + 10, height + 6, |x, y| {
+            let inside = (5..width + 5).contains(&x) && (2..height + 2).contains(&y);
+            Rgb(if inside { color } else { [(x % 3) as u8, 0, 0] })
+        })
+        .save(&path)
+        .unwrap();
+        path
+    };
+    let inputs = [
+        bordered("1.png", 300, 200, RED),
+        bordered("2.png", 500, 200, GREEN),
+    ];
+    let dzi = dir.path().join("out/tiles.dzi");
+    let mut args: Vec<OsString> = inputs.iter().map(|p| p.clone().into()).collect();
+    args.extend(["--output".into(), dzi.clone().into()]);
+    args.extend(["--format", "png", "--autotrim", "--trim-tolerance", "2"].map(Into::into));
+    zoomtiler_ok(args);
+
+    let xml = std::fs::read_to_string(dzi).unwrap();
+    assert!(xml.contains(r#"<Size Width="800" Height="200"/>"#));
+    let base = open_image(&dir.path().join("out/tiles_files/10/0_0.png")).to_rgb8();
+    assert_eq!(base.get_pixel(0, 0), &Rgb(RED));
+    assert_eq!(base.get_pixel(299, 199), &Rgb(RED));
+    assert_eq!(base.get_pixel(300, 0), &Rgb(GREEN));
+}
+
+#[test]
+fn trim_edges_crops_duplicated_seam_columns() {
+    let dir = tempfile::tempdir().unwrap();
+    // Slices whose edge columns are white, like seams duplicated in both neighbors.
+    let slice = |name: &str, width: u32, color: [u8; 3]| {
+        let path = dir.path().join(name);
+        image::RgbImage::from_fn(width, 200, |x, _| {
+            Rgb(if x == 0 || x == width - 1 {
+                [255; 3]
+            } else {
+                color
+            })
+        })
+        .save(&path)
+        .unwrap();
+        path
+    };
+    let inputs = [
+        slice("1.png", 300, RED),
+        slice("2.png", 500, GREEN),
+        slice("3.png", 250, BLUE),
+    ];
+    let dzi = dir.path().join("out/tiles.dzi");
+    let args = |edges: &str| {
+        let mut args: Vec<OsString> = inputs.iter().map(|p| p.clone().into()).collect();
+        args.extend(["--output".into(), dzi.clone().into()]);
+        args.extend(
+            [
+                "--format",
+                "png",
+                "--tilesize",
+                "2048",
+                "--trim-edges",
+                edges,
+            ]
+            .map(Into::into),
+        );
+        args
+    };
+    zoomtiler_ok(args("1,1;1,1;1,0"));
+    let xml = std::fs::read_to_string(&dzi).unwrap();
+    assert!(
+        xml.contains(r#"<Size Width="1045" Height="200"/>"#),
+        "{}",
+        xml
+    );
+    let base = open_image(&dir.path().join("out/tiles_files/11/0_0.png")).to_rgb8();
+    assert_eq!(base.get_pixel(0, 0), &Rgb(RED));
+    assert_eq!(base.get_pixel(297, 0), &Rgb(RED));
+    assert_eq!(base.get_pixel(298, 0), &Rgb(GREEN));
+    assert_eq!(base.get_pixel(795, 0), &Rgb(GREEN));
+    assert_eq!(base.get_pixel(796, 0), &Rgb(BLUE));
+    assert_eq!(base.get_pixel(1044, 0), &Rgb([255; 3]));
+
+    // A single pair applies to all inputs.
+    zoomtiler_ok(args("1,1"));
+    let xml = std::fs::read_to_string(&dzi).unwrap();
+    assert!(
+        xml.contains(r#"<Size Width="1044" Height="200"/>"#),
+        "{}",
+        xml
+    );
+
+    let failure = |edges: &str| {
+        let output = zoomtiler(args(edges));
+        assert!(!output.status.success());
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+    let stderr = failure("0,0;1,1");
+    assert!(
+        stderr.contains("There are 2 edge trims for 3 images"),
+        "{}",
+        stderr
+    );
+    let stderr = failure("100,150");
+    assert!(
+        stderr.contains("3.png has width 250, trimming 100,150 edge columns would leave nothing"),
+        "{}",
+        stderr
+    );
+    let stderr = failure("1,1,1");
+    assert!(stderr.contains("Invalid --trim-edges"), "{}", stderr);
+}
+
+#[test]
+fn common_width_centers_each_image() {
+    let dir = tempfile::tempdir().unwrap();
+    let narrow = solid_image(dir.path(), "1.png", 300, 200, RED);
+    // A 500 wide image, blue on its first 100 columns and green elsewhere.
+    let wide = dir.path().join("2.png");
+    image::RgbImage::from_fn(500, 200, |x, _| Rgb(if x < 100 { BLUE } else { GREEN }))
+        .save(&wide)
+        .unwrap();
+    let dzi = dir.path().join("out/tiles.dzi");
+    let mut args: Vec<OsString> = vec![narrow.into(), wide.into()];
+    args.extend(["--output".into(), dzi.clone().into()]);
+    args.extend(
+        [
+            "--format",
+            "png",
+            "--tilesize",
+            "1024",
+            "--common-width",
+            "400",
+        ]
+        .map(Into::into),
+    );
+    zoomtiler_ok(args);
+
+    let xml = std::fs::read_to_string(dzi).unwrap();
+    assert!(xml.contains(r#"<Size Width="800" Height="200"/>"#));
+    let base = open_image(&dir.path().join("out/tiles_files/10/0_0.png")).to_rgb8();
+    // The narrow image is padded with 50 background columns on each side.
+    assert_eq!(base.get_pixel(49, 0), &Rgb([0, 0, 0]));
+    assert_eq!(base.get_pixel(50, 0), &Rgb(RED));
+    assert_eq!(base.get_pixel(349, 199), &Rgb(RED));
+    assert_eq!(base.get_pixel(350, 0), &Rgb([0, 0, 0]));
+    // The wide image loses 50 columns on each side.
+    assert_eq!(base.get_pixel(400, 0), &Rgb(BLUE));
+    assert_eq!(base.get_pixel(449, 0), &Rgb(BLUE));
+    assert_eq!(base.get_pixel(450, 0), &Rgb(GREEN));
+    assert_eq!(base.get_pixel(799, 199), &Rgb(GREEN));
+}
+
+/// Record the resolution of a png image in a pHYs chunk, right after its header.
+fn set_png_dpi(path: &std::path::Path, dpi: f64) {
+    let mut png = std::fs::read(path).unwrap();
+    let pixels_per_meter = ((dpi / 0.0254).round() as u32).to_be_bytes();
+    let mut chunk = 9u32.to_be_bytes().to_vec();
+    chunk.extend_from_slice(b"pHYs");
+    chunk.extend_from_slice(&pixels_per_meter);
+    chunk.extend_from_slice(&pixels_per_meter);
+    chunk.push(1);
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(&chunk[4..]);
+    chunk.extend_from_slice(&crc.finalize().to_be_bytes());
+    let after_ihdr = 8 + 13 + 12;
+    png.splice(after_ihdr..after_ihdr, chunk);
+    std::fs::write(path, png).unwrap();
+}
+
+#[test]
+fn dpi_is_read_from_png_and_jpg_headers() {
+    let dir = tempfile::tempdir().unwrap();
+    let png = solid_image(dir.path(), "1.png", 30, 20, RED);
+    assert_eq!(read_dpi(&std::fs::read(&png).unwrap()), None);
+    set_png_dpi(&png, 300.0);
+    let dpi = read_dpi(&std::fs::read(&png).unwrap()).unwrap();
+    assert!((dpi - 300.0).abs() < 0.01, "{}", dpi);
+    // JFIF header with a density of 72 pixels per inch.
+    let jfif = b"\xff\xd8\xff\xe0\x00\x10JFIF\x00\x01\x01\x01\x00\x48\x00\x48\x00\x00";
+    assert_eq!(read_dpi(jfif), Some(72.0));
+}
+
+#[test]
+fn normalize_dpi_resizes_inputs_to_the_target_resolution() {
+    let dir = tempfile::tempdir().unwrap();
+    let inputs = [
+        solid_image(dir.path(), "1.png", 300, 200, RED),
+        solid_image(dir.path(), "2.png", 600, 400, GREEN),
+        solid_image(dir.path(), "3.png", 100, 200, BLUE),
+    ];
+    set_png_dpi(&inputs[0], 150.0);
+    set_png_dpi(&inputs[1], 300.0);
+    let dzi = dir.path().join("out/tiles.dzi");
+    let mut args: Vec<OsString> = inputs.iter().map(|p| p.clone().into()).collect();
+    args.extend(["--output".into(), dzi.clone().into()]);
+    args.extend(
+        [
+            "--format",
+            "png",
+            "--tilesize",
+            "1024",
+            "--normalize-dpi",
+            "150",
+        ]
+        .map(Into::into),
+    );
+    let output = zoomtiler_ok(args);
+    // The last image has no dpi metadata and is kept as is.
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("3.png has no dpi metadata"), "{}", stderr);
+
+    let xml = std::fs::read_to_string(dzi).unwrap();
+    assert!(
+        xml.contains(r#"<Size Width="700" Height="200"/>"#),
+        "{}",
+        xml
+    );
+    let base = open_image(&dir.path().join("out/tiles_files/10/0_0.png")).to_rgb8();
+    assert_eq!(base.get_pixel(299, 199), &Rgb(RED));
+    assert_eq!(base.get_pixel(300, 0), &Rgb(GREEN));
+    assert_eq!(base.get_pixel(599, 199), &Rgb(GREEN));
+    assert_eq!(base.get_pixel(600, 0), &Rgb(BLUE));
+}
+
+#[test]
+fn rotated_inputs_are_stitched_in_their_new_orientation() {
+    let dir = tempfile::tempdir().unwrap();
+    // A landscape slice, red on its left half and green on its right half.
+    let landscape = dir.path().join("landscape.png");
+    image::RgbImage::from_fn(200, 100, |x, _| Rgb(if x < 100 { RED } else { GREEN }))
+        .save(&landscape)
+        .unwrap();
+    let inputs = [solid_image(dir.path(), "1.png", 50, 200, BLUE), landscape];
+    let dzi = dir.path().join("out/tiles.dzi");
+    let args = |rotations: &str| {
+        let mut args: Vec<OsString> = inputs.iter().map(|p| p.clone().into()).collect();
+        args.extend(["--output".into(), dzi.clone().into()]);
+        args.extend(["--format", "png", "--rotate", rotations].map(Into::into));
+        args
+    };
+    zoomtiler_ok(args("0,90"));
+    let xml = std::fs::read_to_string(&dzi).unwrap();
+    assert!(
+        xml.contains(r#"<Size Width="150" Height="200"/>"#),
+        "{}",
+        xml
+    );
+
+    // Turned clockwise, the left half of the slice is at the top.
+    let base = open_image(&dir.path().join("out/tiles_files/8/0_0.png")).to_rgb8();
+    assert_eq!(base.dimensions(), (150, 200));
+    assert_eq!(base.get_pixel(49, 199), &Rgb(BLUE));
+    assert_eq!(base.get_pixel(50, 99), &Rgb(RED));
+    assert_eq!(base.get_pixel(149, 100), &Rgb(GREEN));
+
+    let output = zoomtiler(args("90"));
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("There are 1 rotations for 2 images"),
+        "{}",
+        stderr
+    );
+}
+
+/// Write a corrupt png file whose header has a zero width.
+fn zero_width_png(path: &std::path::Path) {
+    let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+    png.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 200, 8, 2, 0, 0, 0]);
+    png.extend_from_slice(&[0; 4]);
+    std::fs::write(path, png).unwrap();
+}
+
+#[test]
+fn inputs_with_invalid_sizes_are_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let corrupt = dir.path().join("corrupt.png");
+    zero_width_png(&corrupt);
+    let inputs = [
+        solid_image(dir.path(), "1.png", 300, 200, RED),
+        corrupt,
+        solid_image(dir.path(), "2.png", 500, 200, GREEN),
+    ];
+    let dzi = dir.path().join("out/tiles.dzi");
+    let mut args: Vec<OsString> = inputs.iter().map(|p| p.clone().into()).collect();
+    args.extend(["--output".into(), dzi.clone().into()]);
+    args.extend(["--offsets", "0,5,0"].map(Into::into));
+    let output = zoomtiler_ok(args.clone());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("corrupt.png has an invalid size 0x200"),
+        "{}",
+        stderr
+    );
+    let xml = std::fs::read_to_string(&dzi).unwrap();
+    assert!(
+        xml.contains(r#"<Size Width="800" Height="200"/>"#),
+        "{}",
+        xml
+    );
+
+    // They are errors in strict mode.
+    args.push("--strict".into());
+    let output = zoomtiler(args);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("corrupt.png has an invalid size 0x200"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn strict_heights_are_checked_with_the_tolerance() {
+    let dir = tempfile::tempdir().unwrap();
+    let inputs = [
+        solid_image(dir.path(), "1.png", 300, 200, RED),
+        solid_image(dir.path(), "2.png", 300, 195, GREEN),
+    ];
+    let dzi = dir.path().join("out/tiles.dzi");
+    // The first image is 5 pixels, or 2.6%, higher than the 195 pixels of the panorama.
+    let tile = |extra_args: &[&str]| {
+        let mut args: Vec<OsString> = inputs.iter().map(|p| p.clone().into()).collect();
+        args.extend(["--output".into(), dzi.clone().into(), "--strict".into()]);
+        args.extend(extra_args.iter().map(Into::into));
+        zoomtiler(args)
+    };
+    for tolerance in ["5", "3%"] {
+        let output = tile(&["--height-tolerance", tolerance]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{}: {}", tolerance, stderr);
+    }
+    for extra_args in [
+        &[][..],
+        &["--height-tolerance", "4"],
+        &["--height-tolerance", "2%"],
+    ] {
+        let output = tile(extra_args);
+        assert!(!output.status.success(), "{:?}", extra_args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("1.png has height 200 but the panorama height is 195"),
+            "{}",
+            stderr
+        );
+    }
+}
+
+#[test]
+fn explicit_heights_pad_shorter_images_with_the_background() {
+    let dir = tempfile::tempdir().unwrap();
+    let inputs = [
+        solid_image(dir.path(), "1.png", 300, 200, RED),
+        solid_image(dir.path(), "2.png", 500, 180, GREEN),
+    ];
+    let dzi = dir.path().join("out/tiles.dzi");
+    let mut args: Vec<OsString> = inputs.iter().map(|p| p.clone().into()).collect();
+    args.extend(["--output".into(), dzi.clone().into()]);
+    args.extend(
+        [
+            "--format",
+            "png",
+            "--heights",
+            "200,100",
+            "--background",
+            "0000ff",
+        ]
+        .map(Into::into),
+    );
+    zoomtiler_ok(args);
+
+    let xml = std::fs::read_to_string(dzi).unwrap();
+    assert!(xml.contains(r#"<Size Width="800" Height="200"/>"#));
+    let base = open_image(&dir.path().join("out/tiles_files/10/0_0.png")).to_rgb8();
+    assert_eq!(base.get_pixel(0, 199), &Rgb(RED));
+    assert_eq!(base.get_pixel(300, 99), &Rgb(GREEN));
+    assert_eq!(base.get_pixel(300, 100), &Rgb(BLUE));
+}
+
+#[test]
+fn reference_image_gives_the_panorama_height() {
+    let dir = tempfile::tempdir().unwrap();
+    let inputs = [
+        solid_image(dir.path(), "1.png", 100, 250, RED),
+        solid_image(dir.path(), "2.png", 100, 200, GREEN),
+        solid_image(dir.path(), "3.png", 100, 150, RED),
+    ];
+    let dzi = dir.path().join("out/tiles.dzi");
+    for reference in [inputs[1].clone().into_os_string(), "1".into()] {
+        let mut args: Vec<OsString> = inputs.iter().map(|p| p.clone().into()).collect();
+        args.extend(["--output".into(), dzi.clone().into()]);
+        args.extend(["--format", "png", "--background", "0000ff"].map(Into::into));
+        args.extend(["--reference-height-from".into(), reference]);
+        zoomtiler_ok(args);
+
+        let xml = std::fs::read_to_string(&dzi).unwrap();
+        assert!(
+            xml.contains(r#"<Size Width="300" Height="200"/>"#),
+            "{}",
+            xml
+        );
+        let base = open_image(&dir.path().join("out/tiles_files/9/0_0.png")).to_rgb8();
+        assert_eq!(base.get_pixel(0, 199), &Rgb(RED));
+        assert_eq!(base.get_pixel(200, 149), &Rgb(RED));
+        assert_eq!(base.get_pixel(200, 150), &Rgb(BLUE));
+    }
+}
+
+#[test]
+fn reference_image_must_be_an_input() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = solid_image(dir.path(), "1.png", 100, 100, RED);
+    for reference in ["other.png", "1"] {
+        let output = zoomtiler([
+            input.as_os_str(),
+            "--output".as_ref(),
+            dir.path().join("out/tiles.dzi").as_os_str(),
+            "--reference-height-from".as_ref(),
+            reference.as_ref(),
+        ]);
+        assert!(!output.status.success(), "{}", reference);
+    }
+}
+
+#[test]
+fn explicit_heights_cannot_exceed_image_heights() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = solid_image(dir.path(), "1.png", 300, 200, RED);
+    let args: [OsString; 5] = [
+        input.into(),
+        "--output".into(),
+        dir.path().join("out/tiles.dzi").into(),
+        "--heights".into(),
+        "201".into(),
+    ];
+    assert!(!zoomtiler(args).status.success());
+}
+
+#[test]
+fn summary_and_progress_are_printed_unless_quiet() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = solid_image(dir.path(), "1.png", 600, 200, RED);
+    let args = |dzi: &str, quiet: bool| {
+        let mut args: Vec<OsString> = vec![input.clone().into(), "--output".into()];
+        args.push(dir.path().join(dzi).into());
+        args.extend(["--tilesize", "256"].map(Into::into));
+        if quiet {
+            args.push("--quiet".into());
+        }
+        args
+    };
+    // 3 tiles for the base level, 2 for the next one and 1 for the 9 others.
+    let output = zoomtiler_ok(args("out/tiles.dzi", false));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("11 levels, 14 tiles, "), "{}", stderr);
+    assert!(stderr.contains("tile_size: 256\n"), "{}", stderr);
+    assert!(stderr.contains("height: 200\n"), "{}", stderr);
+    assert!(
+        stderr.contains("Using image 0 for tile (2, 0)"),
+        "{}",
+        stderr
+    );
+    let output = zoomtiler_ok(args("quiet/tiles.dzi", true));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains(" levels, "), "{}", stderr);
+    assert!(!stderr.contains("levels: "), "{}", stderr);
+    assert!(!stderr.contains("tile_size: "), "{}", stderr);
+    assert!(!stderr.contains("height: "), "{}", stderr);
+    assert!(!stderr.contains("Using image"), "{}", stderr);
+}
+
+#[test]
+fn huge_panorama_fails_early() {
+    let dir = tempfile::tempdir().unwrap();
+    let inputs = [
+        solid_image(dir.path(), "1.png", 300, 200, RED),
+        solid_image(dir.path(), "2.png", 500, 200, GREEN),
+    ];
+    let dzi = dir.path().join("out/tiles.dzi");
+    let mut args: Vec<OsString> = inputs.iter().map(|p| p.clone().into()).collect();
+    args.extend(["--output".into(), dzi.clone().into()]);
+    args.extend(["--gap", "5000000000"].map(Into::into));
+    let output = zoomtiler(args);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("5000000800x200 pixels is too large"),
+        "{}",
+        stderr
+    );
+    assert!(!dzi.exists());
+}
+
+#[test]
+fn tiles_of_the_skip_color_are_not_written() {
+    let dir = tempfile::tempdir().unwrap();
+    let inputs = [
+        solid_image(dir.path(), "1.png", 512, 256, [250, 255, 255]),
+        solid_image(dir.path(), "2.png", 256, 256, RED),
+    ];
+    let dzi = dir.path().join("out/tiles.dzi");
+    let tile = |level: usize, name: &str| {
+        dir.path()
+            .join(format!("out/tiles_files/{}/{}", level, name))
+    };
+    let mut args: Vec<OsString> = inputs.iter().map(|p| p.clone().into()).collect();
+    args.extend(["--output".into(), dzi.into()]);
+    args.extend(["--format", "png", "--tilesize", "256"].map(Into::into));
+    zoomtiler_ok(&args);
+    assert!(tile(10, "0_0.png").is_file());
+
+    args.extend(["--skip-color", "ffffff", "--skip-tolerance", "5"].map(Into::into));
+    zoomtiler_ok(&args);
+    // The tiles of the previous build are removed.
+    assert!(!tile(10, "0_0.png").exists());
+    assert!(!tile(10, "1_0.png").exists());
+    assert!(tile(10, "2_0.png").is_file());
+    // Coarser tiles entirely built from skipped tiles are skipped too,
+    // and the others have their normal size, with the skip color in skipped areas.
+    assert!(!tile(9, "0_0.png").exists());
+    assert!(tile(9, "1_0.png").is_file());
+    let coarse = open_image(&tile(8, "0_0.png")).to_rgb8();
+    assert_eq!(coarse.dimensions(), (192, 64));
+    assert_eq!(coarse.get_pixel(0, 0), &Rgb([255, 255, 255]));
+    assert_eq!(coarse.get_pixel(191, 0), &Rgb(RED));
+
+    // Verifying expects the skipped tiles to be missing, but not the others.
+    let verify = || zoomtiler(["verify".into(), dir.path().join("out/tiles.dzi")]);
+    let output = verify();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    std::fs::remove_file(tile(10, "2_0.png")).unwrap();
+    let output = verify();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("1 tiles are missing or corrupt"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn skip_color_is_not_used_with_the_debug_grid() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = solid_image(dir.path(), "1.png", 300, 200, RED);
+    for grid in ["--debug-grid", "--debug-grid-all-levels"] {
+        let output = zoomtiler([
+            input.as_os_str(),
+            "--output".as_ref(),
+            dir.path().join("out/tiles.dzi").as_os_str(),
+            grid.as_ref(),
+            "--skip-color".as_ref(),
+            "ff0000".as_ref(),
+        ]);
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("--debug-grid cannot be used with --skip-color"),
+            "{}",
+            stderr
+        );
+    }
+}
+
+#[test]
+fn low_memory_tiles_match_the_cached_ones() {
+    let dir = tempfile::tempdir().unwrap();
+    let gradient = dir.path().join("gradient.png");
+    image::RgbImage::from_fn(300, 700, |x, y| Rgb([x as u8, y as u8, (x + y) as u8]))
+        .save(&gradient)
+        .unwrap();
+    let deep = dir.path().join("deep.png");
+    let deep_img: image::ImageBuffer<Rgb<u16>, _> =
+        image::ImageBuffer::from_fn(200, 650, |x, y| Rgb([x as u16 * 300, y as u16 * 100, 4000]));
+    deep_img.save(&deep).unwrap();
+    let inputs = [
+        gradient,
+        solid_image(dir.path(), "solid.jpg", 150, 680, GREEN),
+        deep,
+    ];
+    let tile = |name: &str, low_memory: bool| {
+        let dzi = dir.path().join(name).join("tiles.dzi");
+        let mut args: Vec<OsString> = inputs.iter().map(|p| p.clone().into()).collect();
+        args.extend(["--output".into(), dzi.into()]);
+        args.extend(["--format", "png", "--tilesize", "128"].map(Into::into));
+        args.extend(["--offsets", "0,-20,15", "--common-width", "250"].map(Into::into));
+        if low_memory {
+            args.push("--low-memory".into());
+        }
+        zoomtiler_ok(args)
+    };
+    tile("cached", false);
+    let output = tile("streamed", true);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Streaming image 0 for tile (0, 0)"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("Streaming image 2 for tile (5, 5)"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("Using image 1 for tile (2, 0)"),
+        "{}",
+        stderr
+    );
+
+    let cached_dir = dir.path().join("cached/tiles_files");
+    let streamed_dir = dir.path().join("streamed/tiles_files");
+    assert_eq!(entries(&cached_dir), entries(&streamed_dir));
+    for level in entries(&cached_dir) {
+        let tiles = entries(&cached_dir.join(&level));
+        assert_eq!(tiles, entries(&streamed_dir.join(&level)));
+        for tile in tiles {
+            let path = PathBuf::from(&level).join(tile);
+            let cached = open_image(&cached_dir.join(&path)).to_rgb8();
+            let streamed = open_image(&streamed_dir.join(&path)).to_rgb8();
+            assert!(cached == streamed, "Tile {} differs", path.display());
+        }
+    }
+}
+
+#[test]
+fn low_memory_needs_whole_images_to_feather() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = solid_image(dir.path(), "1.png", 300, 200, RED);
+    let output = zoomtiler([
+        input.as_os_str(),
+        "--output".as_ref(),
+        dir.path().join("out/tiles.dzi").as_os_str(),
+        "--feather".as_ref(),
+        "8".as_ref(),
+        "--low-memory".as_ref(),
+    ]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("--low-memory cannot be used with --feather"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn low_memory_needs_the_column_tile_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = solid_image(dir.path(), "1.png", 300, 200, RED);
+    let output = zoomtiler([
+        input.as_os_str(),
+        "--output".as_ref(),
+        dir.path().join("out/tiles.dzi").as_os_str(),
+        "--tile-order".as_ref(),
+        "row".as_ref(),
+        "--low-memory".as_ref(),
+    ]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("--low-memory can only be used with the column --tile-order"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn low_memory_warns_about_inputs_decoded_whole() {
+    let dir = tempfile::tempdir().unwrap();
+    let streamed = solid_image(dir.path(), "1.png", 300, 200, RED);
+    let whole = solid_image(dir.path(), "2.bmp", 300, 200, GREEN);
+    let output = zoomtiler_ok([
+        streamed.as_os_str(),
+        whole.as_os_str(),
+        "--output".as_ref(),
+        dir.path().join("out/tiles.dzi").as_os_str(),
+        "--low-memory".as_ref(),
+    ]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let warning = "is decoded whole despite --low-memory, since it is not a png image";
+    assert_eq!(stderr.matches(warning).count(), 1, "{}", stderr);
+    assert!(stderr.contains("2.bmp"), "{}", stderr);
+}
@@ -0,0 +1,155 @@
+// SPDX-License-Identifier: MPL-2.0
+
+mod common;
+
+use common::{entries, solid_image, zoomtiler, zoomtiler_ok};
+use std::path::Path;
+
+/// Tile a 1000x300 image into 256 pixels png tiles, with levels 0 to 10.
+fn tile(dir: &Path) {
+    tile_with(dir, &[]);
+}
+
+fn tile_with(dir: &Path, extra_args: &[&str]) {
+    let input = solid_image(dir, "input.png", 1000, 300, [10, 20, 30]);
+    let mut args = vec![
+        input.into_os_string(),
+        "--output".into(),
+        dir.join("out/tiles.dzi").into_os_string(),
+    ];
+    args.extend(["--format", "png", "--tilesize", "256"].map(Into::into));
+    args.extend(extra_args.iter().map(Into::into));
+    zoomtiler_ok(args);
+}
+
+/// Check that the tileset only has the given levels, and that verify accepts it.
+fn assert_valid_levels(dir: &Path, levels: &[&str]) {
+    let files_dir = dir.join("out/tiles_files");
+    assert_eq!(entries(&files_dir), levels);
+    zoomtiler_ok(["verify".as_ref(), dir.join("out/tiles.dzi").as_os_str()]);
+}
+
+#[test]
+fn complete_tileset_is_valid() {
+    let dir = tempfile::tempdir().unwrap();
+    tile(dir.path());
+    zoomtiler_ok([
+        "verify".as_ref(),
+        dir.path().join("out/tiles.dzi").as_os_str(),
+    ]);
+}
+
+#[test]
+fn missing_and_corrupt_tiles_are_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    tile(dir.path());
+    let files_dir = dir.path().join("out/tiles_files");
+    std::fs::remove_file(files_dir.join("10/3_1.png")).unwrap();
+    std::fs::write(files_dir.join("9/0_0.png"), b"not a png").unwrap();
+    solid_image(&files_dir.join("0"), "0_0.png", 2, 1, [0, 0, 0]);
+
+    let output = zoomtiler([
+        "verify".as_ref(),
+        dir.path().join("out/tiles.dzi").as_os_str(),
+    ]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("tile (3, 1) of level 10: missing"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("tile (0, 0) of level 9: corrupt"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("tile (0, 0) of level 0: size 2x1 instead of 1x1"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("3 tiles are missing or corrupt"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn levels_stopping_at_a_single_tile_are_valid() {
+    let dir = tempfile::tempdir().unwrap();
+    tile_with(dir.path(), &["--level-strategy", "tile-count"]);
+    assert_valid_levels(dir.path(), &["10", "8", "9"]);
+}
+
+#[test]
+fn base_only_levels_are_valid() {
+    let dir = tempfile::tempdir().unwrap();
+    tile_with(dir.path(), &["--base-only"]);
+    assert_valid_levels(dir.path(), &["10"]);
+}
+
+#[test]
+fn discarded_levels_are_not_expected() {
+    let dir = tempfile::tempdir().unwrap();
+    tile_with(dir.path(), &["--discard-levels", "3"]);
+    assert_valid_levels(dir.path(), &["0", "1", "2", "3"]);
+
+    // The kept levels are still checked.
+    std::fs::remove_file(dir.path().join("out/tiles_files/3/0_0.png")).unwrap();
+    let output = zoomtiler([
+        "verify".as_ref(),
+        dir.path().join("out/tiles.dzi").as_os_str(),
+    ]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("1 tiles are missing or corrupt"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn custom_files_suffix_is_referenced_by_the_dzi() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = solid_image(dir.path(), "input.png", 300, 200, [10, 20, 30]);
+    let dzi_path = dir.path().join("out/image.dzi");
+    zoomtiler_ok([
+        input.as_os_str(),
+        "--output".as_ref(),
+        dzi_path.as_os_str(),
+        "--format".as_ref(),
+        "png".as_ref(),
+        "--files-suffix".as_ref(),
+        ".dzi_files".as_ref(),
+    ]);
+    assert!(dir.path().join("out/image.dzi_files/9/0_0.png").is_file());
+    assert!(!dir.path().join("out/image_files").exists());
+    let xml = std::fs::read_to_string(&dzi_path).unwrap();
+    assert!(xml.contains(r#" Url="image.dzi_files/""#), "{}", xml);
+    zoomtiler_ok(["verify".as_ref(), dzi_path.as_os_str()]);
+}
+
+#[test]
+fn absolute_files_suffix_is_the_tiles_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = solid_image(dir.path(), "input.png", 300, 200, [10, 20, 30]);
+    let dzi_path = dir.path().join("out/image.dzi");
+    let tiles_dir = dir.path().join("data");
+    zoomtiler_ok([
+        input.as_os_str(),
+        "--output".as_ref(),
+        dzi_path.as_os_str(),
+        "--format".as_ref(),
+        "png".as_ref(),
+        "--files-suffix".as_ref(),
+        tiles_dir.as_os_str(),
+    ]);
+    assert!(tiles_dir.join("9/0_0.png").is_file());
+    let xml = std::fs::read_to_string(&dzi_path).unwrap();
+    let url = format!(" Url=\"{}/\"", tiles_dir.display());
+    assert!(xml.contains(&url), "{}", xml);
+    zoomtiler_ok(["verify".as_ref(), dzi_path.as_os_str()]);
+}